@@ -1,7 +1,9 @@
 use std;
+use std::cmp::Reverse;
 use rayon;
 use rayon::prelude::*;
 use utils;
+use profile::Profiler;
 
 #[inline(always)]
 fn arr_length_at_depth(base_length: usize, depth: usize) -> usize {
@@ -24,7 +26,7 @@ fn parent(i: usize) -> usize {
 }
 
 #[derive(Debug, Clone)]
-struct ArrayTree<T> {
+pub struct ArrayTree<T> {
     layers: Box<[Box<[T]>]>
 }
 struct ArrayTreeView<'a, T: 'a> {
@@ -173,8 +175,8 @@ impl<T> ArrayTree<T> {
     fn depth(&self) -> usize {
         1 + self.layers.len()
     }
-    fn layers(&self) -> &Box<[Box<[T]>]> {
-        & self.layers
+    pub fn layers(&self) -> &[Box<[T]>] {
+        &self.layers
     }
     pub fn layers_mut(&mut self) -> &mut Box<[Box<[T]>]> {
         &mut self.layers
@@ -182,7 +184,7 @@ impl<T> ArrayTree<T> {
 }
 
 
-fn get_left_opt( tree_view: &mut ArrayTreeView<usize>, real_idx: usize, start: usize) -> isize {
+fn get_left_opt<T: Ord + Copy>(tree_view: &mut ArrayTreeView<T>, real_idx: usize, start: usize) -> isize {
     let value = *tree_view.val_at(0, real_idx);
     tree_view.go_to_bottom();
     tree_view.go_to_idx(0);
@@ -222,7 +224,7 @@ fn get_left_opt( tree_view: &mut ArrayTreeView<usize>, real_idx: usize, start: u
     tree_view.cur_idx as isize
 }
 
-fn get_right_opt( tree_view: &mut ArrayTreeView<usize>, real_idx: usize, start: usize) -> isize {
+fn get_right_opt<T: Ord + Copy>(tree_view: &mut ArrayTreeView<T>, real_idx: usize, start: usize) -> isize {
     let value = *tree_view.val_at(0, real_idx);
     tree_view.go_to_bottom();
     tree_view.go_to_idx(0);
@@ -262,12 +264,13 @@ fn get_right_opt( tree_view: &mut ArrayTreeView<usize>, real_idx: usize, start:
     tree_view.cur_idx as isize
 }
 
-fn compute_ansv_linear(indices: &[usize], left_nearest_neighbors: &mut[isize], right_nearest_neighbors: &mut [isize], offset: usize) {
+// Equal values don't count as smaller, so anything equal gets popped along with the larger values.
+fn compute_ansv_linear<T: Ord>(indices: &[T], left_nearest_neighbors: &mut[isize], right_nearest_neighbors: &mut [isize], offset: usize) {
     let mut unsafe_stack = utils::UncheckedFixedSizeStack::<usize>::new(indices.len());
 
     // depends on state of stack -- only works in serial
     let mut get_nearest_neighbor = |stack: &mut utils::UncheckedFixedSizeStack<usize>, idx, dest: &mut isize| {
-        while stack.len() > 0 && indices[unsafe {*stack.peek()}] > indices[idx] {
+        while stack.len() > 0 && indices[unsafe {*stack.peek()}] >= indices[idx] {
             unsafe {
                 stack.pop();
             }
@@ -288,10 +291,10 @@ fn compute_ansv_linear(indices: &[usize], left_nearest_neighbors: &mut[isize], r
     right_nearest_neighbors.iter_mut().enumerate().rev().for_each(|(idx, val)| get_nearest_neighbor(&mut unsafe_stack,idx, val));
 }
 
-fn construct_min_search_tree(base: &[usize]) -> ArrayTree<usize> {
-    let mut min_tree = ArrayTree::<usize>::new(base);
+pub fn construct_min_search_tree<T>(base: &[T]) -> ArrayTree<T> where T: Ord + Copy + Default + Send + Sync {
+    let mut min_tree = ArrayTree::<T>::new(base);
 
-    let mut update_row = |child_row: &[usize], cur_row: &mut[usize]| {
+    let update_row = |child_row: &[T], cur_row: &mut[T]| {
         let skip_end = if child_row.len() % 2 == 1 {
             1
         } else {
@@ -310,6 +313,11 @@ fn construct_min_search_tree(base: &[usize]) -> ArrayTree<usize> {
         let depth = min_tree.depth();
         let mut other_rows = min_tree.layers_mut();
 
+        // Nothing above the base layer when it has a single element.
+        if depth == 1 {
+            return min_tree;
+        }
+
         update_row(base, other_rows.first_mut().unwrap().as_mut());
 
         for d in 2..depth {
@@ -322,19 +330,33 @@ fn construct_min_search_tree(base: &[usize]) -> ArrayTree<usize> {
 
 }
 
-pub fn compute_ansv(indices: &[usize]) -> (Box<[isize]>, Box<[isize]>) {
+// Nearest strictly smaller values, -1 where there is none.
+pub fn compute_ansv<T>(indices: &[T]) -> (Box<[isize]>, Box<[isize]>) where T: Ord + Copy + Default + Send + Sync {
+    compute_ansv_profiled(indices, &Profiler::new())
+}
+
+// The phase compute_ansv_profiled records its chunks under, which callers should time it as too.
+pub const ANSV_PHASE: &str = "ANSV Arrays";
+
+// compute_ansv, recording every chunk with the profiler.
+pub fn compute_ansv_profiled<T>(indices: &[T], profiler: &Profiler) -> (Box<[isize]>, Box<[isize]>)
+    where T: Ord + Copy + Default + Send + Sync {
     let indices_len = indices.len();
 
-    let mut left_nearest_neighbors = vec![0isize; indices_len].into_boxed_slice();
-    let mut right_nearest_neighbors = vec![0isize; indices_len].into_boxed_slice();
+    let mut left_nearest_neighbors = vec![-1isize; indices_len].into_boxed_slice();
+    let mut right_nearest_neighbors = vec![-1isize; indices_len].into_boxed_slice();
+
+    if indices_len <= 1 {
+        return (left_nearest_neighbors, right_nearest_neighbors);
+    }
 
     let min_tree = construct_min_search_tree(indices);
 
-    let chunk_size = utils::rayon_chunk_size(indices_len);
+    let chunk_size = utils::rayon_chunk_size(indices_len).max(1);
 
     generic_izip!(indices.par_chunks(chunk_size), left_nearest_neighbors.par_chunks_mut(chunk_size), right_nearest_neighbors.par_chunks_mut(chunk_size))
         .enumerate()
-        .for_each( |(idx, (indices_chunk, lnn_chunk, rnn_chunk)): (usize, (&[usize], &mut [isize], &mut [isize]))| {
+        .for_each( |(idx, (indices_chunk, lnn_chunk, rnn_chunk))| profiler.chunk(ANSV_PHASE, indices_chunk.len(), || {
 
             let mut tree_view = ArrayTreeView::new(indices, &min_tree);
 
@@ -365,12 +387,71 @@ pub fn compute_ansv(indices: &[usize]) -> (Box<[isize]>, Box<[isize]>) {
                 }
                 chunk_rnn_idx
             });
-        }
+        })
     );
 
     (left_nearest_neighbors, right_nearest_neighbors)
 }
 
+// For every position, the position of its nearest value on one side, if there is one.
+pub type NearestValues = Box<[Option<usize>]>;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nearest {
+    Smaller,
+    SmallerOrEqual,
+    Larger,
+    LargerOrEqual
+}
+
+fn to_options(nearest_neighbors: &[isize]) -> Box<[Option<usize>]> {
+    nearest_neighbors.par_iter().map(|&idx| if idx == -1 {
+        None
+    } else {
+        Some(idx as usize)
+    }).collect::<Vec<Option<usize>>>().into_boxed_slice()
+}
+
+// The general version of compute_ansv, which only finds strictly smaller values.
+//     Everything else is turned into that problem by changing what we compare. Reverse turns larger into
+// smaller. For the "or equal" versions we pair every value with its position, so that out of two equal
+// values the one we're searching towards compares as smaller: on the left the earlier position is smaller
+// as is, on the right we need the later position to be smaller so it gets reversed. That costs a copy of
+// the input and a second tree, since left and right need different keys.
+//     The CLI only needs compute_ansv, this one is for the library modules.
+#[allow(dead_code)]
+pub fn nearest_values<T>(values: &[T], nearest: Nearest) -> (NearestValues, NearestValues)
+    where T: Ord + Copy + Default + Send + Sync {
+
+    fn left_and_right<L, R>(left_keys: &[L], right_keys: &[R]) -> (Box<[isize]>, Box<[isize]>)
+        where L: Ord + Copy + Default + Send + Sync, R: Ord + Copy + Default + Send + Sync {
+        let (left_nearest_neighbors, right_nearest_neighbors) = rayon::join(
+            || compute_ansv(left_keys).0,
+            || compute_ansv(right_keys).1);
+        (left_nearest_neighbors, right_nearest_neighbors)
+    }
+
+    let (left_nearest_neighbors, right_nearest_neighbors) = match nearest {
+        Nearest::Smaller => compute_ansv(values),
+        Nearest::Larger => {
+            compute_ansv(values.par_iter().map(|&v| Reverse(v)).collect::<Vec<Reverse<T>>>().as_ref())
+        },
+        Nearest::SmallerOrEqual => {
+            let left_keys = values.par_iter().enumerate().map(|(idx, &v)| (v, idx)).collect::<Vec<(T, usize)>>();
+            let right_keys = values.par_iter().enumerate().map(|(idx, &v)| (v, Reverse(idx))).collect::<Vec<(T, Reverse<usize>)>>();
+            left_and_right(left_keys.as_ref(), right_keys.as_ref())
+        },
+        Nearest::LargerOrEqual => {
+            let left_keys = values.par_iter().enumerate().map(|(idx, &v)| (Reverse(v), idx)).collect::<Vec<(Reverse<T>, usize)>>();
+            let right_keys = values.par_iter().enumerate().map(|(idx, &v)| (Reverse(v), Reverse(idx))).collect::<Vec<(Reverse<T>, Reverse<usize>)>>();
+            left_and_right(left_keys.as_ref(), right_keys.as_ref())
+        }
+    };
+
+    (to_options(&left_nearest_neighbors), to_options(&right_nearest_neighbors))
+}
+
 #[cfg(test)]
 mod test {
     use std;
@@ -481,6 +562,47 @@ mod test {
 
         validate_ansv(data.as_ref(), lnn.as_ref(), rnn.as_ref());
     }
+    fn naive_nearest_values(data: &[u8], nearest: super::Nearest) -> (Vec<Option<usize>>, Vec<Option<usize>>) {
+        let matches = |candidate: u8, value: u8| match nearest {
+            super::Nearest::Smaller => candidate < value,
+            super::Nearest::SmallerOrEqual => candidate <= value,
+            super::Nearest::Larger => candidate > value,
+            super::Nearest::LargerOrEqual => candidate >= value
+        };
+        let left = (0..data.len()).map(|idx| (0..idx).rev().find(|&j| matches(data[j], data[idx]))).collect();
+        let right = (0..data.len()).map(|idx| (idx + 1..data.len()).find(|&j| matches(data[j], data[idx]))).collect();
+        (left, right)
+    }
+
+    #[test]
+    fn nearest_values_test() {
+        // Lots of repeated values, which the suffix array never has.
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE / 16 + 3, 16);
+        for &nearest in [super::Nearest::Smaller, super::Nearest::SmallerOrEqual, super::Nearest::Larger, super::Nearest::LargerOrEqual].iter() {
+            let (lnn, rnn) = super::nearest_values(data.as_ref(), nearest);
+            let (naive_lnn, naive_rnn) = naive_nearest_values(data.as_ref(), nearest);
+            assert_eq!(lnn.as_ref(), naive_lnn.as_slice());
+            assert_eq!(rnn.as_ref(), naive_rnn.as_slice());
+        }
+    }
+
+    #[test]
+    fn nearest_values_small_test() {
+        let (lnn, rnn) = super::nearest_values(&[3, 1, 3, 2, 3][..], super::Nearest::Smaller);
+        assert_eq!(lnn.as_ref(), &[None, None, Some(1), Some(1), Some(3)]);
+        assert_eq!(rnn.as_ref(), &[Some(1), None, Some(3), None, None]);
+
+        let (lnn, rnn) = super::nearest_values(&[3, 1, 3, 2, 3][..], super::Nearest::LargerOrEqual);
+        assert_eq!(lnn.as_ref(), &[None, Some(0), Some(0), Some(2), Some(2)]);
+        assert_eq!(rnn.as_ref(), &[Some(2), Some(2), Some(4), Some(4), None]);
+
+        let (lnn, rnn) = super::nearest_values(&[5usize][..], super::Nearest::Smaller);
+        assert_eq!((lnn.as_ref(), rnn.as_ref()), (&[None][..], &[None][..]));
+
+        let (lnn, rnn) = super::nearest_values(&[] as &[usize], super::Nearest::Smaller);
+        assert_eq!((lnn.len(), rnn.len()), (0, 0));
+    }
+
     #[test]
     fn ansv_linear_test() {
         let mut data = (0usize..utils::DEFAULT_TEST_SIZE).collect::<Vec<usize>>().into_boxed_slice();
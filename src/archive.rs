@@ -0,0 +1,384 @@
+use std;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use utils::{read_u64, write_u64};
+use container::Container;
+use error::Error;
+use rayon::prelude::*;
+
+// Explanation:
+// - Format:
+//     ARCHIVE_MAGIC, then (little endian u64s) the flags, the number of entries and every entry, each its
+// kind, mode, modification time (seconds since the epoch), length and path (length, then the UTF-8 bytes).
+// Paths are relative, '/' separated, and a directory comes before anything inside it. After the entries are
+// the containers: one for a solid archive, with the n-th file as its n-th document, otherwise one per file.
+//
+// - Solid or not:
+//     A solid archive factorizes every file at once, so a file can copy from any other one, which is what
+// makes a directory full of similar files small. Since no phrase crosses a document boundary (see
+// documents.rs) any one file still decodes on its own. Per-file archives give up the matches between files
+// for never holding more than one file's suffix array at a time.
+//
+// - Metadata:
+//     We keep the permission bits (just read-only outside unix) and the modification time, and put the bits
+// back on extraction. std can't set modification times, so those are only there for listing. Anything that's
+// not a file or a directory (symlinks included) is left out.
+
+const ARCHIVE_MAGIC: &[u8; 4] = b"GRPA";
+const SOLID_FLAG: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub modified: u64,
+    pub len: usize
+}
+
+fn corrupt() -> Error {
+    Error::Corrupt("Archive is corrupt.".to_owned())
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    std::fs::set_permissions(path, permissions)
+}
+
+pub fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(ARCHIVE_MAGIC)
+}
+
+// Whether a path starts with a Windows drive, like "C:" (which "C:foo" is relative to, not us).
+fn has_drive_prefix(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+// Archived paths can't climb out of wherever they're extracted to, on any platform: Windows takes '\' as a
+// separator too, so a component with one in it could still be "..\..".
+fn check_path(path: &str) -> Result<(), Error> {
+    let components_ok = path.split('/')
+        .all(|component| !component.is_empty() && component != "." && component != ".." && !component.contains('\\'));
+    if components_ok && !has_drive_prefix(path) {
+        Ok(())
+    } else {
+        Err(Error::Corrupt(format!("Refusing to use the archived path {:?}.", path)))
+    }
+}
+
+fn walk_into(path: &Path, name: String, out: &mut Vec<(Entry, PathBuf)>) -> Result<(), Error> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let modified = metadata.modified()?.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let kind = if metadata.is_dir() {
+        EntryKind::Directory
+    } else if metadata.is_file() {
+        EntryKind::File
+    } else {
+        return Ok(());
+    };
+    out.push((Entry {
+        path: name.clone(),
+        kind,
+        mode: mode_of(&metadata),
+        modified,
+        len: if kind == EntryKind::File { metadata.len() as usize } else { 0 }
+    }, path.to_path_buf()));
+
+    if kind == EntryKind::Directory {
+        let mut children = std::fs::read_dir(path)?.map(|child| child.map(|child| child.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        children.sort();
+        for child in children.iter() {
+            let child_name = child.file_name().and_then(|child_name| child_name.to_str())
+                .ok_or_else(|| Error::Format(format!("{} isn't valid UTF-8.", child.display())))?;
+            if child_name.contains('\\') {
+                return Err(Error::Format(format!("{} can't be extracted on Windows, its name has a '\\' in it.", child.display())));
+            }
+            walk_into(child.as_path(), format!("{}/{}", name, child_name), out)?;
+        }
+    }
+    Ok(())
+}
+
+// Every file and directory under the roots, each named relative to its root's parent (like tar), along with
+// where it is on disk.
+pub fn walk(roots: &[&Path]) -> Result<Vec<(Entry, PathBuf)>, Error> {
+    let mut out = Vec::new();
+    for root in roots.iter() {
+        let name = root.file_name().and_then(|name| name.to_str())
+            .ok_or_else(|| Error::Format(format!("Can't archive {} under its own name.", root.display())))?;
+        walk_into(root, name.to_owned(), &mut out)?;
+    }
+    Ok(out)
+}
+
+pub struct Archive {
+    entries: Box<[Entry]>,
+    solid: bool,
+    containers: Box<[Container]>
+}
+
+impl Archive {
+    // contents holds every file entry's bytes, in order, and compress turns a list of documents into a
+    // container with one document each.
+    pub fn new<F>(entries: Box<[Entry]>, contents: &[&[u8]], solid: bool, compress: F) -> Result<Archive, Error>
+        where F: Fn(&[&[u8]]) -> Result<Container, Error> {
+        // The lengths come from walk, so a file that doesn't match changed while we were reading it.
+        let files = entries.iter().filter(|entry| entry.kind == EntryKind::File);
+        if let Some(entry) = files.zip(contents.iter()).find(|&(entry, file)| entry.len != file.len()).map(|(entry, _)| entry) {
+            return Err(Error::Io(std::io::Error::other(format!("{} changed while it was being archived.", entry.path))));
+        }
+        let containers = if solid {
+            vec![compress(contents)?]
+        } else {
+            contents.iter().map(|&file| compress(&[file])).collect::<Result<Vec<Container>, _>>()?
+        };
+        Archive::from_parts(entries, solid, containers.into_boxed_slice())
+    }
+
+    fn from_parts(entries: Box<[Entry]>, solid: bool, containers: Box<[Container]>) -> Result<Archive, Error> {
+        let archive = Archive {
+            entries,
+            solid,
+            containers
+        };
+        for entry in archive.entries.iter() {
+            check_path(entry.path.as_ref())?;
+        }
+        let file_lens = archive.entries.iter().filter(|entry| entry.kind == EntryKind::File)
+            .map(|entry| entry.len).collect::<Vec<usize>>();
+        let lens_ok = if solid {
+            archive.containers.len() == 1
+                && archive.containers[0].documents().iter().map(|document| document.len).eq(file_lens.iter().cloned())
+        } else {
+            archive.containers.iter().map(|container| container.len()).eq(file_lens.iter().cloned())
+        };
+        if !lens_ok {
+            return Err(corrupt());
+        }
+        Ok(archive)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        self.entries.as_ref()
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.solid
+    }
+
+    pub fn compressed_len(&self) -> usize {
+        self.containers.iter().map(|container| container.payload_len()).sum()
+    }
+
+    // The bytes of the file-th file (counting files only).
+    pub fn file_contents(&self, file: usize) -> Result<Box<[u8]>, Error> {
+        if self.solid {
+            self.containers[0].extract_document(file)
+        } else {
+            match self.containers.get(file) {
+                Some(container) => container.decompress(),
+                None => Err(Error::Format(format!("There are only {} files.", self.containers.len())))
+            }
+        }
+    }
+
+    // Checks every container against its checksums, saying which file is wrong if they're separate.
+    pub fn verify(&self) -> Result<(), Error> {
+        let files = self.entries.iter().filter(|entry| entry.kind == EntryKind::File).collect::<Vec<&Entry>>();
+        self.containers.par_iter().enumerate().map(|(idx, container)| {
+            container.verify(None).map_err(|err| match err {
+                Error::Corrupt(message) if !self.solid => Error::Corrupt(format!("{}: {}", files[idx].path, message)),
+                err => err
+            })
+        }).collect::<Result<Vec<()>, Error>>()?;
+        Ok(())
+    }
+
+    // Extracts every entry which is one of the paths or inside one of them (or everything, with no paths)
+    // into dest.
+    pub fn unpack(&self, dest: &Path, paths: &[&str]) -> Result<(), Error> {
+        let wanted = |entry: &Entry| paths.is_empty() || paths.iter().any(|&path| {
+            let path = path.trim_end_matches('/');
+            entry.path == path || entry.path.starts_with(path) && entry.path[path.len()..].starts_with('/')
+        });
+        let on_disk = |entry: &Entry| entry.path.split('/').fold(dest.to_path_buf(), |path, component| path.join(component));
+
+        // Directories first, since files go inside them, but their modes last, in case they're read-only.
+        let mut files = Vec::new();
+        let mut file = 0;
+        for entry in self.entries.iter() {
+            match entry.kind {
+                EntryKind::Directory if wanted(entry) => std::fs::create_dir_all(on_disk(entry))?,
+                EntryKind::File => {
+                    if wanted(entry) {
+                        files.push((file, entry));
+                    }
+                    file += 1;
+                }
+                _ => {}
+            }
+        }
+
+        files.par_iter().map(|&(file, entry)| {
+            let path = on_disk(entry);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let data = self.file_contents(file)?;
+            std::fs::File::create(path.as_path())?.write_all(data.as_ref())?;
+            set_mode(path.as_path(), entry.mode)?;
+            Ok(())
+        }).collect::<Result<Vec<()>, Error>>()?;
+
+        for entry in self.entries.iter().filter(|entry| entry.kind == EntryKind::Directory && wanted(entry)) {
+            set_mode(on_disk(entry).as_path(), entry.mode)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(ARCHIVE_MAGIC)?;
+        write_u64(writer, if self.solid { SOLID_FLAG } else { 0 })?;
+        write_u64(writer, self.entries.len() as u64)?;
+        for entry in self.entries.iter() {
+            write_u64(writer, match entry.kind { EntryKind::File => 0, EntryKind::Directory => 1 })?;
+            write_u64(writer, entry.mode as u64)?;
+            write_u64(writer, entry.modified)?;
+            write_u64(writer, entry.len as u64)?;
+            write_u64(writer, entry.path.len() as u64)?;
+            writer.write_all(entry.path.as_bytes())?;
+        }
+        write_u64(writer, self.containers.len() as u64)?;
+        for container in self.containers.iter() {
+            container.write_to(writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Archive, Error> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != ARCHIVE_MAGIC {
+            return Err(Error::Format("Not an archive.".to_owned()));
+        }
+        let solid = read_u64(reader)? & SOLID_FLAG != 0;
+        let entries_len = read_u64(reader)? as usize;
+        let entries = (0..entries_len).map(|_| {
+            let kind = match read_u64(reader)? {
+                0 => EntryKind::File,
+                1 => EntryKind::Directory,
+                _ => return Err(corrupt())
+            };
+            let mode = read_u64(reader)? as u32;
+            let modified = read_u64(reader)?;
+            let len = read_u64(reader)? as usize;
+            let mut path = vec![0u8; read_u64(reader)? as usize];
+            reader.read_exact(path.as_mut())?;
+            Ok(Entry {
+                path: String::from_utf8(path).map_err(|_| corrupt())?,
+                kind,
+                mode,
+                modified,
+                len
+            })
+        }).collect::<Result<Vec<Entry>, Error>>()?.into_boxed_slice();
+        let containers_len = read_u64(reader)? as usize;
+        let containers = (0..containers_len).map(|_| Container::read_from(reader))
+            .collect::<Result<Vec<Container>, _>>()?.into_boxed_slice();
+        Archive::from_parts(entries, solid, containers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils;
+    use super::{Archive, Entry, EntryKind};
+
+    fn entry(path: &str, kind: EntryKind, len: usize) -> Entry {
+        Entry {
+            path: path.to_owned(),
+            kind,
+            mode: 0o644,
+            modified: 1234,
+            len
+        }
+    }
+
+    fn round_trip(solid: bool) {
+        let base = utils::random_slice_from_alphabet(4096, 4);
+        let files = [base[..1000].to_vec(), Vec::new(), base[500..4096].to_vec(), base[..].to_vec()];
+        let contents = files.iter().map(|file| file.as_slice()).collect::<Vec<&[u8]>>();
+        let entries = vec![
+            entry("dir", EntryKind::Directory, 0),
+            entry("dir/a", EntryKind::File, files[0].len()),
+            entry("dir/empty", EntryKind::File, 0),
+            entry("dir/sub", EntryKind::Directory, 0),
+            entry("dir/sub/b", EntryKind::File, files[2].len()),
+            entry("c", EntryKind::File, files[3].len())
+        ];
+        let archive = Archive::new(entries.clone().into_boxed_slice(), contents.as_ref(), solid, |documents| ::compress_documents(documents, ::level::Level::default(), &::profile::Profiler::new())).unwrap();
+
+        let mut bytes = Vec::new();
+        archive.write_to(&mut bytes).unwrap();
+        let read = Archive::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.entries(), entries.as_slice());
+        assert_eq!(read.is_solid(), solid);
+        for (file, expected) in files.iter().enumerate() {
+            assert_eq!(read.file_contents(file).unwrap().as_ref(), expected.as_slice());
+        }
+    }
+
+    #[test]
+    fn solid_round_trip_test() {
+        round_trip(true);
+    }
+
+    #[test]
+    fn per_file_round_trip_test() {
+        round_trip(false);
+    }
+
+    #[test]
+    fn unsafe_paths_test() {
+        for &path in ["../up", "/absolute", "a/./b", "a//b", "", "a\\..\\..\\up", "..\\up", "C:", "C:/Windows", "c:up"].iter() {
+            let entries = vec![entry(path, EntryKind::Directory, 0)].into_boxed_slice();
+            assert!(Archive::new(entries, &[], true, |documents| ::compress_documents(documents, ::level::Level::default(), &::profile::Profiler::new())).is_err());
+        }
+        assert!(super::check_path("a/b..c/d").is_ok());
+        assert!(super::check_path("a/c:d").is_ok());
+    }
+
+    #[test]
+    fn changed_file_test() {
+        let entries = vec![entry("a", EntryKind::File, 10)].into_boxed_slice();
+        let result = Archive::new(entries, &[b"shorter".as_ref()], false, |documents| ::compress_documents(documents, ::level::Level::default(), &::profile::Profiler::new()));
+        assert_eq!(result.err().unwrap().exit_code(), 2);
+    }
+}
@@ -0,0 +1,264 @@
+use utils;
+use rayon::prelude::*;
+use error::Error;
+
+// Explanation:
+// - Burrows-Wheeler transform:
+//     Sort every rotation of data + "$", where "$" is a sentinel smaller than every byte, and take the last
+// column. With the sentinel, sorting rotations is the same as sorting suffixes, so row 0 is the rotation
+// starting at "$" and row i + 1 is the rotation starting at suffix_array[i]. The last character of each row
+// is the one just before where it starts, so the whole thing is a single parallel map over the suffix array.
+//     The sentinel isn't a byte, so like bzip2 and divsufsort we leave it out of the output and return the row
+// it would've been on instead (the primary index). Everything below works in "row" space, which is the
+// output with the sentinel put back in.
+//
+// - Inverse:
+//     The LF mapping takes the row starting at text position p to the row starting at p - 1: for the k-th
+// occurrence of c in the last column it's C[c] + k, where C[c] counts the characters smaller than c
+// (including the sentinel). Starting from row 0 and following LF spells the text backwards, ending at the
+// primary row.
+//     Building LF is a counting sort, so it's done in chunks: count every chunk, scan the counts, then fill
+// every chunk in at once. Following it is one long chain through every row though, so we split it with a
+// ruling set: every RULER_SPACING-th row is a ruler, and every ruler walks LF (in parallel) until it hits the
+// next ruler, keeping what it read. The rows are in suffix order, which has nothing to do with text order,
+// so the walks come out about the same length. Chaining the rulers together from row 0 is then a short
+// serial loop, which tells us where every walk goes in the output.
+//     Nothing stops a file from handing us any bytes and any primary index though. LF is a permutation
+// whatever the bytes are, so the walk from row 0 always ends at the primary row, but unless the bytes really
+// are a transform it ends early and the rows it missed go round in cycles of their own. So the chain has to
+// account for every byte, and every ruler that read something has to be on it, before we write anything.
+
+const RULER_SPACING: usize = 4096;
+
+// Returns (bwt, primary index).
+pub fn bwt(data: &[u8], suffix_array: &[usize]) -> (Box<[u8]>, usize) {
+    if data.is_empty() {
+        return (Box::new([]), 0);
+    }
+    let primary = 1 + suffix_array.par_iter().position_any(|&suffix| suffix == 0).unwrap();
+    let transform = (0..data.len()).into_par_iter().map(|idx| {
+        let row = if idx < primary { idx } else { idx + 1 };
+        if row == 0 {
+            data[data.len() - 1]
+        } else {
+            data[suffix_array[row - 1] - 1]
+        }
+    }).collect::<Vec<u8>>().into_boxed_slice();
+    (transform, primary)
+}
+
+// LF in row space, indexed by position in the bwt (the primary row doesn't have an entry).
+fn lf_array(bwt: &[u8]) -> Box<[usize]> {
+    let chunk_size = utils::rayon_chunk_size(bwt.len()).max(1);
+
+    let chunk_counts = bwt.par_chunks(chunk_size).map(|chunk| {
+        let mut counts = [0usize; 256];
+        chunk.iter().for_each(|&c| counts[c as usize] += 1);
+        counts
+    }).collect::<Vec<[usize; 256]>>();
+
+    // The sentinel is the one row starting with something smaller than every byte.
+    let mut next_row = [0usize; 256];
+    let mut total = 1;
+    for c in 0..256 {
+        next_row[c] = total;
+        total += chunk_counts.iter().map(|counts| counts[c]).sum::<usize>();
+    }
+    let chunk_offsets = chunk_counts.iter().map(|counts| {
+        let offsets = next_row;
+        for c in 0..256 {
+            next_row[c] += counts[c];
+        }
+        offsets
+    }).collect::<Vec<[usize; 256]>>();
+
+    let mut lf = vec![0usize; bwt.len()].into_boxed_slice();
+    lf.par_chunks_mut(chunk_size).zip(bwt.par_chunks(chunk_size)).zip(chunk_offsets.par_iter())
+        .for_each(|((lf_chunk, bwt_chunk), offsets)| {
+            let mut offsets = *offsets;
+            lf_chunk.iter_mut().zip(bwt_chunk.iter()).for_each(|(lf_el, &c)| {
+                *lf_el = offsets[c as usize];
+                offsets[c as usize] += 1;
+            });
+        });
+    lf
+}
+
+pub fn inverse_bwt(bwt: &[u8], primary: usize) -> Result<Box<[u8]>, Error> {
+    if bwt.is_empty() {
+        return Ok(Box::new([]));
+    }
+    // Row 0 is the sentinel's own rotation, so it can't be where the sentinel ends up in the last column.
+    if primary == 0 || primary > bwt.len() {
+        return Err(Error::Corrupt(format!("Primary index {} is out of range for a {} byte transform.", primary, bwt.len())));
+    }
+    let lf = lf_array(bwt);
+    let bwt_idx = |row: usize| if row < primary { row } else { row - 1 };
+    let is_ruler = |row: usize| row == primary || row.is_multiple_of(RULER_SPACING);
+
+    // Row 0 is always a ruler, and the primary row is where everything stops.
+    let walks = (0..bwt.len() / RULER_SPACING + 1).into_par_iter()
+        .map(|ruler| ruler * RULER_SPACING)
+        .map(|ruler_row| {
+            let mut read = Vec::new();
+            let mut row = ruler_row;
+            if row != primary {
+                loop {
+                    let idx = bwt_idx(row);
+                    read.push(bwt[idx]);
+                    row = lf[idx];
+                    if is_ruler(row) {
+                        break;
+                    }
+                }
+            }
+            (read, row)
+        }).collect::<Vec<(Vec<u8>, usize)>>();
+
+    let mut ends = vec![0usize; walks.len()];
+    let mut reached = vec![false; walks.len()];
+    let mut row = 0;
+    let mut pos = bwt.len();
+    while row != primary {
+        let ruler = row / RULER_SPACING;
+        ends[ruler] = pos;
+        reached[ruler] = true;
+        // The walks on the chain never read more than the whole transform between them, so this can't wrap.
+        pos -= walks[ruler].0.len();
+        row = walks[ruler].1;
+    }
+    let unreached = walks.iter().zip(reached.iter()).any(|((read, _), &reached)| !reached && !read.is_empty());
+    if pos != 0 || unreached {
+        return Err(Error::Corrupt("Not a Burrows-Wheeler transform with this primary index.".to_owned()));
+    }
+
+    let text = vec![0u8; bwt.len()].into_boxed_slice();
+    walks.par_iter().zip(ends.par_iter()).for_each(|((read, _), &end)| {
+        read.iter().enumerate().for_each(|(i, &c)| {
+            unsafe {
+                *(text.as_ptr() as *mut u8).add(end - 1 - i) = c;
+            }
+        });
+    });
+    Ok(text)
+}
+
+// The bzip2 stage after the transform: runs of the same byte turn into runs of zeroes.
+pub fn move_to_front(data: &[u8]) -> Box<[u8]> {
+    let mut order = (0..256).map(|c| c as u8).collect::<Vec<u8>>();
+    data.iter().map(|&c| {
+        let rank = order.iter().position(|&o| o == c).unwrap();
+        order[..rank + 1].rotate_right(1);
+        rank as u8
+    }).collect::<Vec<u8>>().into_boxed_slice()
+}
+
+// Order 0 empirical entropy of the data in bits, i.e. about what an ideal entropy coder would write.
+pub fn entropy_bits(data: &[u8]) -> f64 {
+    let counts = data.par_chunks(utils::rayon_chunk_size(data.len()).max(1)).map(|chunk| {
+        let mut counts = [0usize; 256];
+        chunk.iter().for_each(|&c| counts[c as usize] += 1);
+        counts
+    }).reduce(|| [0usize; 256], |mut l, r| {
+        for c in 0..256 {
+            l[c] += r[c];
+        }
+        l
+    });
+    let len = data.len() as f64;
+    counts.iter().filter(|&&count| count > 0).map(|&count| {
+        let count = count as f64;
+        -count * (count / len).log2()
+    }).sum::<f64>()
+}
+
+#[cfg(test)]
+mod test {
+    use utils;
+    use saxx;
+    use test;
+
+    fn suffix_array(data: &[u8]) -> Box<[usize]> {
+        let esa = saxx::Esaxx::<i64>::esaxx(data).unwrap();
+        esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>().into_boxed_slice()
+    }
+
+    fn naive_bwt(data: &[u8]) -> (Vec<u8>, usize) {
+        // Shift every byte up one so 0 can be the sentinel.
+        let text = data.iter().map(|&c| c as u16 + 1).chain(Some(0)).collect::<Vec<u16>>();
+        let mut rotations = (0..text.len()).map(|start| {
+            text[start..].iter().chain(text[..start].iter()).cloned().collect::<Vec<u16>>()
+        }).collect::<Vec<Vec<u16>>>();
+        rotations.sort();
+        let last = rotations.iter().map(|rotation| *rotation.last().unwrap()).collect::<Vec<u16>>();
+        let primary = last.iter().position(|&c| c == 0).unwrap();
+        (last.iter().filter(|&&c| c != 0).map(|&c| (c - 1) as u8).collect(), primary)
+    }
+
+    #[test]
+    fn bwt_fixed_test() {
+        let data = b"banana";
+        let (transform, primary) = super::bwt(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        assert_eq!(transform.as_ref(), b"annbaa".as_ref());
+        assert_eq!(primary, 4);
+        assert_eq!(super::inverse_bwt(transform.as_ref(), primary).unwrap().as_ref(), data.as_ref());
+    }
+
+    #[test]
+    fn bwt_naive_test() {
+        for _ in 0..64 {
+            let data = utils::random_slice_from_alphabet(128, 3);
+            let (transform, primary) = super::bwt(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+            let (naive_transform, naive_primary) = naive_bwt(data.as_ref());
+            assert_eq!(transform.as_ref(), naive_transform.as_slice());
+            assert_eq!(primary, naive_primary);
+        }
+    }
+
+    #[test]
+    fn inverse_bwt_test() {
+        let data = utils::random_slice::<u8>(utils::DEFAULT_TEST_SIZE);
+        let (transform, primary) = super::bwt(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        assert_eq!(super::inverse_bwt(transform.as_ref(), primary).unwrap(), data);
+
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE);
+        let (transform, primary) = super::bwt(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        assert_eq!(super::inverse_bwt(transform.as_ref(), primary).unwrap(), data);
+
+        let data = [7u8];
+        let (transform, primary) = super::bwt(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        assert_eq!(super::inverse_bwt(transform.as_ref(), primary).unwrap().as_ref(), data.as_ref());
+    }
+
+    #[test]
+    fn inverse_bwt_rejects_bad_input_test() {
+        let (transform, _) = super::bwt(b"banana".as_ref(), suffix_array(b"banana".as_ref()).as_ref());
+        assert!(super::inverse_bwt(transform.as_ref(), 0).is_err());
+        assert!(super::inverse_bwt(transform.as_ref(), 999).is_err());
+        // Every byte the same is only a transform with the sentinel at the end.
+        assert!(super::inverse_bwt(b"aaaa".as_ref(), 1).is_err());
+        assert_eq!(super::inverse_bwt(b"aaaa".as_ref(), 4).unwrap().as_ref(), b"aaaa".as_ref());
+
+        // Random bytes are almost never a transform, and big enough to need several rulers.
+        let data = utils::random_slice::<u8>(utils::DEFAULT_TEST_SIZE);
+        for &primary in [1, 5000, data.len() / 2, data.len()].iter() {
+            assert!(super::inverse_bwt(data.as_ref(), primary).is_err());
+        }
+    }
+
+    #[test]
+    fn move_to_front_test() {
+        assert_eq!(super::move_to_front(&[1, 1, 1, 0, 0, 2]).as_ref(), &[1, 0, 0, 1, 0, 2]);
+        assert_eq!(super::entropy_bits(&[5, 5, 5, 5]), 0f64);
+        assert_eq!(super::entropy_bits(&[0, 1, 2, 3]), 8f64);
+    }
+
+    #[bench]
+    fn inverse_bwt_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_slice::<u8>(utils::BENCH_SIZE);
+        let (transform, primary) = super::bwt(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        bencher.iter(|| {
+            super::inverse_bwt(transform.as_ref(), primary)
+        });
+    }
+}
@@ -0,0 +1,341 @@
+// Nothing in the CLI builds these trees yet, they're for the esa module and whoever else needs them.
+#![allow(dead_code)]
+
+use rayon::prelude::*;
+use ansv;
+
+// Explanation:
+// - Cartesian tree:
+//     The (min) Cartesian tree of an array has the position of the minimum at the root, the Cartesian tree
+// of everything to the left of it as its left subtree, and the same for the right. An in-order traversal
+// gives back the positions in order.
+//     Ties are broken by position, so the leftmost of several equal values is the ancestor of the others.
+// That makes every value distinct if we think of it as the pair (value, position).
+//
+// - Parent from ANSV:
+//     The parent of position i is one of its two nearest smaller values (with the tie rule above, the nearest
+// smaller-or-equal on the left and the nearest strictly smaller on the right), specifically whichever of
+// the two is larger. Everything between i and that neighbor is larger than i, so it's in i's subtree, and the
+// neighbor is the first thing above i in the tree on that side. So once ansv has done its job every parent
+// is computed independently of the others, and each child slot of a parent is claimed by exactly one child.
+
+pub struct CartesianTree {
+    pub parent: Box<[Option<usize>]>,
+    pub left_child: Box<[Option<usize>]>,
+    pub right_child: Box<[Option<usize>]>,
+    pub root: Option<usize>
+}
+
+impl CartesianTree {
+    pub fn new<T>(values: &[T]) -> CartesianTree where T: Ord + Copy + Default + Send + Sync {
+        let (left_elements, right_elements) = rayon::join(
+            || ansv::nearest_values(values, ansv::Nearest::SmallerOrEqual).0,
+            || ansv::nearest_values(values, ansv::Nearest::Smaller).1);
+        CartesianTree::from_nearest_values(values, left_elements.as_ref(), right_elements.as_ref())
+    }
+
+    // left_elements must be the nearest smaller-or-equal values on the left and right_elements the nearest
+    // strictly smaller values on the right.
+    pub fn from_nearest_values<T>(values: &[T], left_elements: &[Option<usize>], right_elements: &[Option<usize>]) -> CartesianTree
+        where T: Ord + Sync {
+        let ar_len = values.len();
+
+        let parent = left_elements.par_iter().zip(right_elements.par_iter())
+            .map(|(&left, &right)| {
+                match (left, right) {
+                    (Some(l), Some(r)) => if values[l] > values[r] {
+                        Some(l)
+                    } else {
+                        Some(r)
+                    },
+                    (Some(l), None) => Some(l),
+                    (None, Some(r)) => Some(r),
+                    (None, None) => None
+                }
+            }).collect::<Vec<Option<usize>>>().into_boxed_slice();
+
+        let left_child = vec![None; ar_len].into_boxed_slice();
+        let right_child = vec![None; ar_len].into_boxed_slice();
+
+        parent.par_iter().enumerate().for_each(|(i, &p)| {
+            if let Some(p) = p {
+                let children = if i < p {
+                    &left_child
+                } else {
+                    &right_child
+                };
+                unsafe {
+                    *(children.as_ptr() as *mut Option<usize>).add(p) = Some(i);
+                }
+            }
+        });
+
+        let root = parent.par_iter().position_any(|p| p.is_none());
+
+        CartesianTree {
+            parent,
+            left_child,
+            right_child,
+            root
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parent.len()
+    }
+}
+
+// Explanation:
+// - lcp-interval:
+//     An interval [left..right] of the suffix array where every suffix shares a prefix of length lcp, and
+// which can't be extended in either direction without losing that. In other words lcp[left] < lcp,
+// lcp[right + 1] < lcp, and the minimum of lcp[left + 1..=right] is exactly lcp. These are the internal nodes
+// of the suffix tree, with the interval's children being the lcp-intervals (and single suffixes) directly
+// inside of it, so this tree is a suffix tree without the edge labels.
+//
+// - From the Cartesian tree of the lcp array:
+//     Every position k of the lcp array belongs to exactly one lcp-interval, the one with lcp value lcp[k]
+// containing it. Several positions belong to the same interval when the minimum shows up more than once;
+// we represent the interval by the leftmost of them, which is the one whose nearest smaller-or-equal value on
+// the left is strictly smaller. Its interval runs from there to just before its nearest strictly smaller
+// value on the right. The other positions form a chain of Cartesian tree parents of equal value leading up to
+// the representative, so we find representatives for everyone by pointer jumping up those chains.
+//     The parent interval of a representative is then the interval of its Cartesian tree parent.
+// Position 0 always has lcp 0, so it's the root, and its interval is the whole suffix array.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LcpInterval {
+    pub lcp: usize,
+    pub left: usize,
+    pub right: usize
+}
+
+pub struct LcpIntervalTree {
+    pub intervals: Box<[LcpInterval]>,
+    pub parent: Box<[Option<usize>]>,
+    child_bounds: Box<[usize]>,
+    children: Box<[usize]>
+}
+
+impl LcpIntervalTree {
+    pub fn new(lcp: &[usize]) -> LcpIntervalTree {
+        let ar_len = lcp.len();
+
+        let (left_elements, right_elements) = rayon::join(
+            || ansv::nearest_values(lcp, ansv::Nearest::SmallerOrEqual).0,
+            || ansv::nearest_values(lcp, ansv::Nearest::Smaller).1);
+        let cartesian_tree = CartesianTree::from_nearest_values(lcp, left_elements.as_ref(), right_elements.as_ref());
+
+        let is_representative = left_elements.par_iter().enumerate()
+            .map(|(k, &left)| left.is_none_or(|l| lcp[l] < lcp[k]))
+            .collect::<Vec<bool>>();
+
+        let mut representative = is_representative.par_iter().enumerate()
+            .map(|(k, &is_rep)| if is_rep {
+                k
+            } else {
+                cartesian_tree.parent[k].unwrap()
+            }).collect::<Vec<usize>>();
+        loop {
+            let jumped = representative.par_iter().map(|&r| representative[r]).collect::<Vec<usize>>();
+            if jumped == representative {
+                break;
+            }
+            representative = jumped;
+        }
+
+        let mut node_ids = vec![0usize; ar_len];
+        let n_nodes = is_representative.iter().zip(node_ids.iter_mut()).fold(0, |next_id, (&is_rep, node_id)| {
+            *node_id = next_id;
+            next_id + is_rep as usize
+        });
+
+        let representatives = (0..ar_len).into_par_iter().filter(|&k| is_representative[k]).collect::<Vec<usize>>();
+
+        let intervals = representatives.par_iter().map(|&k| {
+            LcpInterval {
+                lcp: lcp[k],
+                left: left_elements[k].unwrap_or(0),
+                right: right_elements[k].map_or(ar_len - 1, |r| r - 1)
+            }
+        }).collect::<Vec<LcpInterval>>().into_boxed_slice();
+
+        let parent = representatives.par_iter().map(|&k| {
+            cartesian_tree.parent[k].map(|p| node_ids[representative[p]])
+        }).collect::<Vec<Option<usize>>>().into_boxed_slice();
+
+        // Children grouped by parent, in suffix array order since the nodes already are.
+        let mut child_bounds = vec![0usize; n_nodes + 1].into_boxed_slice();
+        parent.iter().for_each(|&p| {
+            if let Some(p) = p {
+                child_bounds[p + 1] += 1;
+            }
+        });
+        (0..n_nodes).for_each(|node| child_bounds[node + 1] += child_bounds[node]);
+
+        let mut children = vec![0usize; n_nodes.saturating_sub(1)].into_boxed_slice();
+        let mut next_slot = child_bounds[..n_nodes].to_vec();
+        parent.iter().enumerate().for_each(|(node, &p)| {
+            if let Some(p) = p {
+                children[next_slot[p]] = node;
+                next_slot[p] += 1;
+            }
+        });
+
+        LcpIntervalTree {
+            intervals,
+            parent,
+            child_bounds,
+            children
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.intervals.len()
+    }
+
+    pub fn root(&self) -> Option<usize> {
+        if self.intervals.is_empty() {
+            None
+        } else {
+            Some(0)
+        }
+    }
+
+    pub fn interval(&self, node: usize) -> &LcpInterval {
+        &self.intervals[node]
+    }
+
+    // Child intervals, left to right. Suffixes in the interval which aren't inside any of these are the
+    // leaf children.
+    pub fn children(&self, node: usize) -> &[usize] {
+        &self.children[self.child_bounds[node]..self.child_bounds[node + 1]]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use utils;
+    use saxx;
+    use lcp;
+    use test;
+
+    fn validate_cartesian_tree(values: &[u8], tree: &super::CartesianTree) {
+        // Heap property, with the leftmost of equal values on top.
+        tree.parent.iter().enumerate().for_each(|(i, &p)| {
+            if let Some(p) = p {
+                assert!((values[p], p) < (values[i], i));
+            }
+        });
+        // In-order traversal gives back the positions in order.
+        let mut in_order = Vec::with_capacity(values.len());
+        let mut stack = Vec::new();
+        let mut cur = tree.root;
+        while cur.is_some() || !stack.is_empty() {
+            while let Some(node) = cur {
+                stack.push(node);
+                cur = tree.left_child[node];
+            }
+            let node = stack.pop().unwrap();
+            in_order.push(node);
+            cur = tree.right_child[node];
+        }
+        assert_eq!(in_order, (0..values.len()).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn cartesian_tree_test() {
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE, 16);
+        let tree = super::CartesianTree::new(data.as_ref());
+        validate_cartesian_tree(data.as_ref(), &tree);
+    }
+
+    #[test]
+    fn cartesian_tree_fixed_test() {
+        let data = [3u8, 1, 4, 1, 5];
+        let tree = super::CartesianTree::new(&data[..]);
+        assert_eq!(tree.root, Some(1));
+        assert_eq!(tree.parent.as_ref(), &[Some(1), None, Some(3), Some(1), Some(3)]);
+        assert_eq!(tree.left_child.as_ref(), &[None, Some(0), None, Some(2), None]);
+        assert_eq!(tree.right_child.as_ref(), &[None, Some(3), None, Some(4), None]);
+    }
+
+    fn naive_lcp_intervals(lcp: &[usize]) -> HashSet<super::LcpInterval> {
+        let mut intervals = HashSet::new();
+        intervals.insert(super::LcpInterval { lcp: 0, left: 0, right: lcp.len() - 1 });
+        for k in (1..lcp.len()).filter(|&k| lcp[k] > 0) {
+            let left = (0..k).rev().find(|&j| lcp[j] < lcp[k]).unwrap();
+            let right = (k + 1..lcp.len()).find(|&j| lcp[j] < lcp[k]).unwrap_or(lcp.len()) - 1;
+            intervals.insert(super::LcpInterval { lcp: lcp[k], left, right });
+        }
+        intervals
+    }
+
+    fn validate_lcp_interval_tree(data: &[u8]) {
+        let esa = saxx::Esaxx::<i64>::esaxx(data).unwrap();
+        let sa = esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>();
+        let lcp = lcp::lcp_array(data, sa.as_ref());
+        let tree = super::LcpIntervalTree::new(lcp.as_ref());
+
+        assert_eq!(tree.intervals.iter().cloned().collect::<HashSet<super::LcpInterval>>(), naive_lcp_intervals(lcp.as_ref()));
+        assert_eq!(tree.intervals.len(), naive_lcp_intervals(lcp.as_ref()).len());
+
+        assert_eq!(tree.root(), Some(0));
+        assert_eq!(tree.parent[0], None);
+        (0..tree.len()).for_each(|node| {
+            let interval = tree.interval(node);
+            let children = tree.children(node);
+            children.iter().for_each(|&child| {
+                let child_interval = tree.interval(child);
+                assert_eq!(tree.parent[child], Some(node));
+                assert!(child_interval.lcp > interval.lcp);
+                assert!(interval.left <= child_interval.left && child_interval.right <= interval.right);
+            });
+            children.iter().zip(children.iter().skip(1)).for_each(|(&l, &r)| {
+                assert!(tree.interval(l).right < tree.interval(r).left);
+            });
+        });
+    }
+
+    #[test]
+    fn lcp_interval_tree_test() {
+        validate_lcp_interval_tree(utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE / 16, 4).as_ref());
+    }
+
+    #[test]
+    fn lcp_interval_tree_repetitive_test() {
+        validate_lcp_interval_tree(&[7u8; 1000][..]);
+        validate_lcp_interval_tree(b"abababababababababababab");
+    }
+
+    #[test]
+    fn lcp_interval_tree_fixed_test() {
+        // Suffix array of banana is [5, 3, 1, 0, 4, 2], lcp is [0, 1, 3, 0, 0, 2].
+        let data = b"banana";
+        let esa = saxx::Esaxx::<i64>::esaxx(data.as_ref()).unwrap();
+        let sa = esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>();
+        let lcp = lcp::lcp_array(data.as_ref(), sa.as_ref());
+        let tree = super::LcpIntervalTree::new(lcp.as_ref());
+
+        assert_eq!(tree.intervals.as_ref(), &[
+            super::LcpInterval { lcp: 0, left: 0, right: 5 },
+            super::LcpInterval { lcp: 1, left: 0, right: 2 },
+            super::LcpInterval { lcp: 3, left: 1, right: 2 },
+            super::LcpInterval { lcp: 2, left: 4, right: 5 }]);
+        assert_eq!(tree.parent.as_ref(), &[None, Some(0), Some(1), Some(0)]);
+        assert_eq!(tree.children(0), &[1, 3]);
+        assert_eq!(tree.children(1), &[2]);
+    }
+
+    #[bench]
+    fn lcp_interval_tree_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_slice::<u8>(utils::BENCH_SIZE);
+        let esa = saxx::Esaxx::<i64>::esaxx(data.as_ref()).unwrap();
+        let sa = esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>();
+        let lcp = lcp::lcp_array(data.as_ref(), sa.as_ref());
+        bencher.iter(|| {
+            super::LcpIntervalTree::new(lcp.as_ref());
+        });
+    }
+}
@@ -0,0 +1,712 @@
+use std;
+use std::io::{Read, Write};
+use utils::{read_u64, write_u64, read_bytes, read_varint, write_varint};
+use documents;
+use checksum;
+use error::Error;
+use level::{Level, Parse, Coder};
+use rayon::prelude::*;
+
+// Explanation:
+// - Format:
+//     CONTAINER_MAGIC, then (little endian u64s) the level it was written with (see level.rs), its coder (0
+// for Varint, 1 for LiteralRuns) and block size in phrases, the text length, the text's CRC32, the number of
+// phrases, the phrase index and the payload. The payload is every
+// phrase one after another, each a varint length followed by either the literal byte (length 0) or a varint
+// distance back to where the copy comes from. The LiteralRuns coder writes runs of literals instead, and
+// tells them apart by the low bit of the length: a varint of len << 1 | 1 and len bytes for a run, and a
+// varint of len << 1 and the distance for a copy, so a lone literal still takes 2 bytes. The phrase index holds (text position, payload offset,
+// CRC32) for the start of every block of level.block_phrases phrases of the factorization, which is where
+// decoding can start from, the CRC32 being of the text that block of phrases decodes to.
+// Last is the document table, (start, length, first phrase, number of phrases) for every document. A single
+// input is one document; a collection factorized with documents::to_symbols has no phrases crossing from one
+// document into the next, so each one is its own range of phrases.
+//     Blocks don't depend on each other, so they're encoded in parallel and glued together, with the index
+// falling out of the block sizes. The level can turn a phrase of the factorization into several (a copy it
+// won't take becomes literals, or shorter copies from inside it), and literal runs can span phrases, so the
+// phrases written aren't the factorization's, but they never cross a block or document boundary.
+//     Everything reading needs is in the header itself, not looked up from the level, so changing what a
+// level means doesn't break files already written with it. Files written before there were levels start
+// with LEGACY_CONTAINER_MAGIC and have no level, coder or block size: they're all Varint, in blocks of
+// LEGACY_BLOCK_PHRASES.
+//
+// - extract(start, len):
+//     Decodes from the last indexed phrase at or before start, but a copy only says where its bytes come
+// from, so for those we go and get that range instead, and so on down the chain until we hit literals. This
+// keeps a stack of ranges still to get (text start, length, where they go in the output) so long chains
+// don't blow the real stack.
+//     A copy can overlap itself (distance < length, e.g. runs), in which case byte p of it is byte
+// start - distance + (p - start) % distance. So we only get the first period of whatever part we need, and
+// remember to tile it over the rest once everything is in. The period's own contents can depend on tiling
+// that's remembered after it (never before), so the tiling is done in reverse.
+//
+// - Checksums:
+//     decompress checks the whole text's CRC32, and verify checks every block's as well, so when something's
+// wrong it can say where. Decoding has to be serial (copies reach back into earlier blocks), but checking
+// the blocks afterwards isn't.
+
+const CONTAINER_MAGIC: &[u8; 4] = b"GRP2";
+const LEGACY_CONTAINER_MAGIC: &[u8; 4] = b"GRIP";
+const LEGACY_BLOCK_PHRASES: usize = 64;
+
+// A literal's offset is where its bytes are: in the text while encoding, in the payload once written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phrase {
+    Literals { offset: usize, len: usize },
+    Copy { len: usize, distance: usize }
+}
+
+impl Phrase {
+    fn len(&self) -> usize {
+        match *self {
+            Phrase::Literals { len, .. } | Phrase::Copy { len, .. } => len
+        }
+    }
+}
+
+fn varint_len(value: usize) -> usize {
+    let mut value = value;
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+// Whether level writes a copy of len bytes from distance back, rather than the bytes themselves.
+fn takes_copy(level: &Level, len: usize, distance: usize) -> bool {
+    if len < level.min_copy {
+        return false;
+    }
+    match level.parse {
+        Parse::Greedy => true,
+        Parse::Cheapest => {
+            // Counting the start of a run, as if the literals were on their own.
+            let (copy_bytes, literal_bytes) = match level.coder {
+                Coder::Varint => (varint_len(len), 2 * len),
+                Coder::LiteralRuns => (varint_len(len << 1), len + 1)
+            };
+            copy_bytes + varint_len(distance) < literal_bytes
+        }
+    }
+}
+
+// Adds the phrases level writes the factorization's phrase [start, end) as to phrases, with their text
+// positions. A literal run only carries on into this phrase if join is set.
+fn push_phrases(data: &[u8], prev_occ: &[isize], start: usize, end: usize, level: &Level, join: bool,
+                phrases: &mut Vec<(usize, Phrase)>) {
+    let mut pos = start;
+    while pos < end {
+        let source = prev_occ[pos];
+        let distance = pos.wrapping_sub(source as usize);
+        // The factorization says how long the copy at the start of the phrase is, anywhere else we look.
+        let copy_len = if source < 0 {
+            0
+        } else if pos == start {
+            end - start
+        } else {
+            (pos..end).zip(source as usize..).take_while(|&(a, b)| data[a] == data[b]).count()
+        };
+        if copy_len > 0 && takes_copy(level, copy_len, distance) {
+            phrases.push((pos, Phrase::Copy { len: copy_len, distance }));
+            pos += copy_len;
+            continue;
+        }
+        let joins = level.coder == Coder::LiteralRuns && (join || pos > start);
+        match phrases.last_mut() {
+            Some(&mut (_, Phrase::Literals { offset, ref mut len })) if joins && offset + *len == pos => *len += 1,
+            _ => phrases.push((pos, Phrase::Literals { offset: pos, len: 1 }))
+        }
+        pos += 1;
+    }
+}
+
+fn corrupt() -> Error {
+    Error::Corrupt("Container is corrupt.".to_owned())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Document {
+    pub start: usize,
+    pub len: usize,
+    pub first_phrase: usize,
+    pub phrase_count: usize
+}
+
+pub struct Container {
+    // None for files written before there were levels.
+    level: Option<u32>,
+    coder: Coder,
+    block_phrases: usize,
+    text_len: usize,
+    checksum: u32,
+    phrase_count: usize,
+    index: Box<[(usize, usize)]>,
+    block_checksums: Box<[u32]>,
+    payload: Box<[u8]>,
+    documents: Box<[Document]>
+}
+
+impl Container {
+    // lz and prev_occ as they come out of lempel_ziv_77, written at the default level. The CLI always has
+    // documents, so only the tests use this.
+    #[cfg(test)]
+    pub fn new(data: &[u8], lz: &[usize], prev_occ: &[isize]) -> Container {
+        Container::with_documents(data, lz, prev_occ, &[data.len()], Level::default())
+    }
+
+    // data is every document one after another, and lens their lengths.
+    pub fn with_documents(data: &[u8], lz: &[usize], prev_occ: &[isize], lens: &[usize], level: Level) -> Container {
+        let starts = documents::document_starts(lens, 0);
+        let lz_phrases = lz.len() - 1;
+        let block_phrases = level.block_phrases;
+        let blocks = (0..lz_phrases.div_ceil(block_phrases)).into_par_iter().map(|block| {
+            let block_end = lz_phrases.min(block * block_phrases + block_phrases);
+            let mut phrases = Vec::new();
+            for phrase in block * block_phrases..block_end {
+                let join = starts.binary_search(&lz[phrase]).is_err();
+                push_phrases(data, prev_occ, lz[phrase], lz[phrase + 1], &level, join, &mut phrases);
+            }
+            let mut out = Vec::new();
+            for &(_, phrase) in phrases.iter() {
+                match phrase {
+                    Phrase::Literals { offset, len } => {
+                        write_varint(&mut out, if level.coder == Coder::LiteralRuns { len << 1 | 1 } else { 0 });
+                        out.extend_from_slice(&data[offset..offset + len]);
+                    }
+                    Phrase::Copy { len, distance } => {
+                        write_varint(&mut out, if level.coder == Coder::LiteralRuns { len << 1 } else { len });
+                        write_varint(&mut out, distance);
+                    }
+                }
+            }
+            let positions = phrases.iter().map(|&(pos, _)| pos).collect::<Vec<usize>>();
+            (out, checksum::crc32_update(0, &data[lz[block * block_phrases]..lz[block_end]]), positions)
+        }).collect::<Vec<(Vec<u8>, u32, Vec<usize>)>>();
+
+        let mut offset = 0;
+        let index = blocks.iter().enumerate().map(|(block, (bytes, _, _))| {
+            let entry = (lz[block * block_phrases], offset);
+            offset += bytes.len();
+            entry
+        }).collect::<Vec<(usize, usize)>>().into_boxed_slice();
+        let block_checksums = blocks.iter().map(|&(_, block_checksum, _)| block_checksum).collect::<Vec<u32>>().into_boxed_slice();
+        let positions = blocks.iter().flat_map(|(_, _, positions)| positions.iter().cloned()).collect::<Vec<usize>>();
+        let phrase_count = positions.len();
+        let payload = blocks.into_iter().map(|(bytes, _, _)| bytes).collect::<Vec<Vec<u8>>>().concat().into_boxed_slice();
+
+        let first_phrases = starts.par_iter().map(|&start| {
+            match positions.binary_search(&start) {
+                Ok(phrase) | Err(phrase) => phrase
+            }
+        }).collect::<Vec<usize>>();
+        let documents = lens.iter().enumerate().map(|(document, &len)| Document {
+            start: starts[document],
+            len,
+            first_phrase: first_phrases[document],
+            phrase_count: first_phrases[document + 1] - first_phrases[document]
+        }).collect::<Vec<Document>>().into_boxed_slice();
+
+        Container {
+            level: Some(level.level),
+            coder: level.coder,
+            block_phrases: level.block_phrases,
+            text_len: data.len(),
+            checksum: checksum::crc32(data),
+            phrase_count,
+            index,
+            block_checksums,
+            payload,
+            documents
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.text_len
+    }
+
+    pub fn level(&self) -> Option<u32> {
+        self.level
+    }
+
+    // Nothing but the tests needs to know how the payload was written.
+    #[cfg(test)]
+    pub fn coder(&self) -> Coder {
+        self.coder
+    }
+
+    #[cfg(test)]
+    pub fn block_phrases(&self) -> usize {
+        self.block_phrases
+    }
+
+    pub fn phrase_count(&self) -> usize {
+        self.phrase_count
+    }
+
+    pub fn payload_len(&self) -> usize {
+        self.payload.len()
+    }
+
+    pub fn documents(&self) -> &[Document] {
+        self.documents.as_ref()
+    }
+
+    pub fn extract_document(&self, document: usize) -> Result<Box<[u8]>, Error> {
+        match self.documents.get(document) {
+            Some(document) => self.extract(document.start, document.len),
+            None => Err(Error::Format(format!("There are only {} documents.", self.documents.len())))
+        }
+    }
+
+    fn read_phrase(&self, offset: &mut usize, text_pos: usize) -> Result<Phrase, Error> {
+        let header = read_varint(self.payload.as_ref(), offset).ok_or_else(corrupt)?;
+        // (length, whether it's literals)
+        let (len, literals) = match self.coder {
+            Coder::Varint if header == 0 => (1, true),
+            Coder::Varint => (header, false),
+            Coder::LiteralRuns => (header >> 1, header & 1 == 1)
+        };
+        if len == 0 {
+            return Err(corrupt());
+        }
+        let phrase = if literals {
+            if len > self.payload.len() - *offset {
+                return Err(corrupt());
+            }
+            *offset += len;
+            Phrase::Literals { offset: *offset - len, len }
+        } else {
+            Phrase::Copy {
+                len,
+                distance: read_varint(self.payload.as_ref(), offset).ok_or_else(corrupt)?
+            }
+        };
+        match phrase {
+            Phrase::Copy { distance, .. } if distance == 0 || distance > text_pos => Err(corrupt()),
+            _ if text_pos + phrase.len() > self.text_len => Err(corrupt()),
+            _ => Ok(phrase)
+        }
+    }
+
+    // Calls f with the text position of every phrase overlapping [start, end), in order.
+    fn for_phrases<F>(&self, start: usize, end: usize, mut f: F) -> Result<(), Error>
+        where F: FnMut(usize, Phrase) {
+        let entry = match self.index.binary_search_by_key(&start, |&(text_pos, _)| text_pos) {
+            Ok(entry) => entry,
+            Err(entry) => entry - 1
+        };
+        let (mut text_pos, mut offset) = self.index[entry];
+        while text_pos < end {
+            let phrase = self.read_phrase(&mut offset, text_pos)?;
+            if text_pos + phrase.len() > start {
+                f(text_pos, phrase);
+            }
+            text_pos += phrase.len();
+        }
+        Ok(())
+    }
+
+    pub fn extract(&self, start: usize, len: usize) -> Result<Box<[u8]>, Error> {
+        if start.checked_add(len).is_none_or(|end| end > self.text_len) {
+            return Err(Error::Format(format!("Can't extract {} bytes at {} from {} bytes.", len, start, self.text_len)));
+        }
+        let mut out = vec![0u8; len].into_boxed_slice();
+        let mut ranges = vec![(start, len, 0)];
+        let mut tilings = Vec::<(usize, usize, usize)>::new();
+
+        while let Some((range_start, range_len, out_offset)) = ranges.pop() {
+            if range_len == 0 {
+                continue;
+            }
+            let range_end = range_start + range_len;
+            self.for_phrases(range_start, range_end, |phrase_start, phrase| {
+                let needed_start = phrase_start.max(range_start);
+                let needed_len = (phrase_start + phrase.len()).min(range_end) - needed_start;
+                let needed_offset = out_offset + needed_start - range_start;
+                match phrase {
+                    Phrase::Literals { offset, .. } => {
+                        let from = offset + needed_start - phrase_start;
+                        out[needed_offset..needed_offset + needed_len].copy_from_slice(&self.payload[from..from + needed_len]);
+                    }
+                    Phrase::Copy { distance, .. } => {
+                        let phase = (needed_start - phrase_start) % distance;
+                        let period_len = needed_len.min(distance);
+                        let before_wrap = period_len.min(distance - phase);
+                        ranges.push((phrase_start - distance + phase, before_wrap, needed_offset));
+                        if period_len > before_wrap {
+                            ranges.push((phrase_start - distance, period_len - before_wrap, needed_offset + before_wrap));
+                        }
+                        if needed_len > distance {
+                            tilings.push((needed_offset, needed_len, distance));
+                        }
+                    }
+                }
+            })?;
+        }
+
+        for &(offset, len, period) in tilings.iter().rev() {
+            for i in offset + period..offset + len {
+                out[i] = out[i - period];
+            }
+        }
+        Ok(out)
+    }
+
+    // The text range block decodes to.
+    fn block_range(&self, block: usize) -> (usize, usize) {
+        (self.index[block].0, self.index.get(block + 1).map_or(self.text_len, |&(text_pos, _)| text_pos))
+    }
+
+    // Decodes everything, or says which block it couldn't decode.
+    fn decode(&self) -> Result<Box<[u8]>, usize> {
+        let mut out = Vec::with_capacity(self.text_len);
+        let mut offset = 0;
+        let mut block = 0;
+        while offset < self.payload.len() {
+            let text_pos = out.len();
+            // Every block has to start on a phrase, where the index says it does.
+            while block + 1 < self.index.len() && self.index[block + 1].1 <= offset {
+                block += 1;
+                if self.index[block] != (text_pos, offset) {
+                    return Err(block);
+                }
+            }
+            match self.read_phrase(&mut offset, text_pos).map_err(|_| block)? {
+                Phrase::Literals { offset, len } => out.extend_from_slice(&self.payload[offset..offset + len]),
+                Phrase::Copy { len, distance } => for i in 0..len {
+                    let byte = out[text_pos + i - distance];
+                    out.push(byte);
+                }
+            }
+        }
+        if out.len() != self.text_len {
+            return Err(block);
+        }
+        Ok(out.into_boxed_slice())
+    }
+
+    fn block_error(&self, block: usize, problem: &str) -> Error {
+        let (start, end) = self.block_range(block);
+        Error::Corrupt(format!("Block {} (bytes {}..{}) {}.", block, start, end, problem))
+    }
+
+    pub fn decompress(&self) -> Result<Box<[u8]>, Error> {
+        let out = self.decode().map_err(|block| self.block_error(block, "is corrupt"))?;
+        if checksum::crc32(out.as_ref()) != self.checksum {
+            return Err(Error::Corrupt("Decompressed data doesn't match its checksum.".to_owned()));
+        }
+        Ok(out)
+    }
+
+    // Decompresses everything and checks every block against its checksum, and against the original if
+    // there is one, saying which block is the first to go wrong.
+    pub fn verify(&self, original: Option<&[u8]>) -> Result<(), Error> {
+        let text = self.decode().map_err(|block| self.block_error(block, "is corrupt"))?;
+        let bad_block = (0..self.index.len()).into_par_iter().filter(|&block| {
+            let (start, end) = self.block_range(block);
+            checksum::crc32_update(0, &text[start..end]) != self.block_checksums[block]
+        }).min();
+        if let Some(block) = bad_block {
+            return Err(self.block_error(block, "doesn't match its checksum"));
+        }
+        if checksum::crc32(text.as_ref()) != self.checksum {
+            return Err(Error::Corrupt("Decompressed data doesn't match its checksum.".to_owned()));
+        }
+        if let Some(original) = original {
+            let different_block = (0..self.index.len()).into_par_iter().filter(|&block| {
+                let (start, end) = self.block_range(block);
+                original.get(start..end) != Some(&text[start..end])
+            }).min();
+            if let Some(block) = different_block {
+                return Err(self.block_error(block, "doesn't match the original"));
+            }
+            if original.len() != text.len() {
+                return Err(Error::Corrupt(format!("Decompressed {} bytes, but the original has {}.", text.len(), original.len())));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        if let Some(level) = self.level {
+            writer.write_all(CONTAINER_MAGIC)?;
+            write_u64(writer, level as u64)?;
+            write_u64(writer, match self.coder { Coder::Varint => 0, Coder::LiteralRuns => 1 })?;
+            write_u64(writer, self.block_phrases as u64)?;
+        } else {
+            writer.write_all(LEGACY_CONTAINER_MAGIC)?;
+        }
+        write_u64(writer, self.text_len as u64)?;
+        write_u64(writer, self.checksum as u64)?;
+        write_u64(writer, self.phrase_count as u64)?;
+        write_u64(writer, self.index.len() as u64)?;
+        for (&(text_pos, offset), &block_checksum) in self.index.iter().zip(self.block_checksums.iter()) {
+            write_u64(writer, text_pos as u64)?;
+            write_u64(writer, offset as u64)?;
+            write_u64(writer, block_checksum as u64)?;
+        }
+        write_u64(writer, self.payload.len() as u64)?;
+        writer.write_all(self.payload.as_ref())?;
+        write_u64(writer, self.documents.len() as u64)?;
+        for document in self.documents.iter() {
+            write_u64(writer, document.start as u64)?;
+            write_u64(writer, document.len as u64)?;
+            write_u64(writer, document.first_phrase as u64)?;
+            write_u64(writer, document.phrase_count as u64)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Container, Error> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let (level, coder, block_phrases) = if &magic == CONTAINER_MAGIC {
+            let level = read_u64(reader)? as u32;
+            let coder = match read_u64(reader)? {
+                0 => Coder::Varint,
+                1 => Coder::LiteralRuns,
+                _ => return Err(corrupt())
+            };
+            (Some(level), coder, read_u64(reader)? as usize)
+        } else if &magic == LEGACY_CONTAINER_MAGIC {
+            (None, Coder::Varint, LEGACY_BLOCK_PHRASES)
+        } else {
+            return Err(Error::Format("Not a compressed file.".to_owned()));
+        };
+        let text_len = read_u64(reader)? as usize;
+        let checksum = read_u64(reader)? as u32;
+        let phrase_count = read_u64(reader)? as usize;
+        let index_len = read_u64(reader)? as usize;
+        let (index, block_checksums): (Vec<(usize, usize)>, Vec<u32>) = (0..index_len)
+            .map(|_| Ok(((read_u64(reader)? as usize, read_u64(reader)? as usize), read_u64(reader)? as u32)))
+            .collect::<std::io::Result<Vec<((usize, usize), u32)>>>()?.into_iter().unzip();
+        let index = index.into_boxed_slice();
+        let payload_len = read_u64(reader)? as usize;
+        let payload = read_bytes(reader, payload_len as u64)?.ok_or_else(corrupt)?.into_boxed_slice();
+        let documents_len = read_u64(reader)? as usize;
+        let documents = (0..documents_len).map(|_| Ok(Document {
+            start: read_u64(reader)? as usize,
+            len: read_u64(reader)? as usize,
+            first_phrase: read_u64(reader)? as usize,
+            phrase_count: read_u64(reader)? as usize
+        })).collect::<std::io::Result<Vec<Document>>>()?.into_boxed_slice();
+
+        // Everything else gets checked as it's decoded, but the index has to be usable to get that far.
+        let index_ok = index.first().map_or(text_len == 0, |&entry| entry == (0, 0))
+            && index.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1)
+            && index.last().is_none_or(|&(text_pos, offset)| text_pos < text_len && offset < payload_len);
+        let documents_ok = documents.iter().all(|document| {
+            document.start.checked_add(document.len).is_some_and(|end| end <= text_len)
+                && document.first_phrase.checked_add(document.phrase_count).is_some_and(|end| end <= phrase_count)
+        });
+        if !index_ok || !documents_ok {
+            return Err(corrupt());
+        }
+
+        Ok(Container {
+            level,
+            coder,
+            block_phrases,
+            text_len,
+            checksum,
+            phrase_count,
+            index,
+            block_checksums: block_checksums.into_boxed_slice(),
+            payload,
+            documents
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils;
+    use level::Level;
+    use rand;
+    use rand::Rng;
+    use test;
+
+    fn compress(data: &[u8]) -> super::Container {
+        let (lz, prev_occ) = ::lempel_ziv_77(data, &::profile::Profiler::new()).unwrap();
+        super::Container::new(data, lz.as_ref(), prev_occ.as_ref())
+    }
+
+    fn validate_extract(data: &[u8]) {
+        let container = compress(data);
+        assert_eq!(container.decompress().unwrap().as_ref(), data);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..utils::DEFAULT_TEST_SAMPLE_SIZE {
+            let start = rng.gen_range(0, data.len());
+            let len = rng.gen_range(0, (data.len() - start).min(4096) + 1);
+            assert_eq!(container.extract(start, len).unwrap().as_ref(), &data[start..start + len]);
+        }
+        assert_eq!(container.extract(0, data.len()).unwrap().as_ref(), data);
+        assert!(container.extract(data.len(), 1).is_err());
+        assert_eq!(container.extract(1, usize::MAX).unwrap_err().exit_code(), 4);
+    }
+
+    #[test]
+    fn extract_random_test() {
+        validate_extract(utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE, 4).as_ref());
+    }
+
+    #[test]
+    fn extract_repetitive_test() {
+        validate_extract(utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE).as_ref());
+    }
+
+    #[test]
+    fn extract_runs_test() {
+        // One long self overlapping copy after the first byte.
+        validate_extract(vec![7u8; utils::DEFAULT_TEST_SIZE].as_ref());
+        validate_extract(b"abcabcabcabcabcabcabcxyzxyzxyzxyzabcabc".as_ref());
+    }
+
+    #[test]
+    fn serialize_test() {
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE);
+        let container = compress(data.as_ref());
+        let mut bytes = Vec::new();
+        container.write_to(&mut bytes).unwrap();
+        let read_container = super::Container::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read_container.decompress().unwrap(), data);
+        assert_eq!(read_container.extract(1000, 100).unwrap().as_ref(), &data[1000..1100]);
+
+        // A payload length past the end of the file, and a document whose end wraps around.
+        let payload_len_at = 4 + 8 * 7 + container.index.len() * 24;
+        let mut huge_payload = bytes.clone();
+        huge_payload[payload_len_at..payload_len_at + 8].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x40]);
+        assert_eq!(super::Container::read_from(&mut huge_payload.as_slice()).err().unwrap().exit_code(), 5);
+        let document_at = payload_len_at + 8 + container.payload_len() + 8;
+        let mut wrapping_document = bytes.clone();
+        wrapping_document[document_at..document_at + 8].copy_from_slice(&[0xff; 8]);
+        wrapping_document[document_at + 8..document_at + 16].copy_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(super::Container::read_from(&mut wrapping_document.as_slice()).err().unwrap().exit_code(), 5);
+
+        bytes[0] = b'Z';
+        assert!(super::Container::read_from(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn legacy_format_test() {
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE);
+        let (lz, prev_occ) = ::lempel_ziv_77(data.as_ref(), &::profile::Profiler::new()).unwrap();
+        // What was written before levels: every copy the factorization found, Varint, in blocks of 64.
+        let legacy = Level { level: 0, parse: super::Parse::Greedy, coder: super::Coder::Varint, min_copy: 1,
+                             block_phrases: super::LEGACY_BLOCK_PHRASES };
+        let mut container = super::Container::with_documents(data.as_ref(), lz.as_ref(), prev_occ.as_ref(), &[data.len()], legacy);
+        container.level = None;
+        let mut bytes = Vec::new();
+        container.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], super::LEGACY_CONTAINER_MAGIC);
+        assert_eq!(utils::read_u64(&mut &bytes[4..12]).unwrap() as usize, data.len());
+
+        let read_container = super::Container::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read_container.level(), None);
+        assert_eq!(read_container.coder(), super::Coder::Varint);
+        assert_eq!(read_container.decompress().unwrap(), data);
+    }
+
+    #[test]
+    fn verify_test() {
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE);
+        let container = compress(data.as_ref());
+        assert!(container.verify(None).is_ok());
+        assert!(container.verify(Some(data.as_ref())).is_ok());
+
+        let mut different = data.to_vec();
+        different[40000] ^= 1;
+        let err = container.verify(Some(different.as_ref())).unwrap_err().to_string();
+        assert!(err.starts_with("Block "), "{}", err);
+        assert!(container.verify(Some(&data[..data.len() - 1])).is_err());
+
+        // Flipping a bit in a literal still decodes, just to the wrong thing.
+        let mut bytes = Vec::new();
+        container.write_to(&mut bytes).unwrap();
+        let payload_start = 4 + 8 * 7 + container.index.len() * 24 + 8;
+        let literal = (payload_start..bytes.len()).find(|&i| bytes[i] == 0).unwrap() + 1;
+        bytes[literal] ^= 1;
+        let corrupted = super::Container::read_from(&mut bytes.as_slice()).unwrap();
+        assert!(corrupted.verify(None).unwrap_err().to_string().contains("checksum"));
+        assert!(corrupted.decompress().is_err());
+    }
+
+    #[test]
+    fn documents_test() {
+        let documents = [b"abcabcabc".to_vec(), Vec::new(), b"xabcabcy".to_vec(), b"abcabcabc".to_vec()];
+        let slices = documents.iter().map(|document| document.as_slice()).collect::<Vec<&[u8]>>();
+        let lens = documents.iter().map(|document| document.len()).collect::<Vec<usize>>();
+        let (lz, prev_occ) = ::lempel_ziv_77(::documents::to_symbols(slices.as_ref()).as_ref(), &::profile::Profiler::new()).unwrap();
+        let (lz, prev_occ) = ::documents::to_byte_space(lens.as_ref(), lz.as_ref(), prev_occ.as_ref());
+        let data = slices.concat();
+        for level in 1..10 {
+            let level = Level::new(level).unwrap();
+            let container = super::Container::with_documents(data.as_ref(), lz.as_ref(), prev_occ.as_ref(), lens.as_ref(), level);
+
+            let mut bytes = Vec::new();
+            container.write_to(&mut bytes).unwrap();
+            let container = super::Container::read_from(&mut bytes.as_slice()).unwrap();
+
+            assert_eq!(container.level(), Some(level.level));
+            assert_eq!(container.coder(), level.coder);
+            assert_eq!(container.block_phrases(), level.block_phrases);
+            assert_eq!(container.documents().len(), documents.len());
+            assert_eq!(container.documents()[1].phrase_count, 0);
+            assert_eq!(container.documents().iter().map(|document| document.phrase_count).sum::<usize>(), container.phrase_count());
+            for (idx, document) in documents.iter().enumerate() {
+                assert_eq!(container.extract_document(idx).unwrap().as_ref(), document.as_slice());
+            }
+            assert!(container.extract_document(documents.len()).is_err());
+        }
+    }
+
+    #[test]
+    fn levels_test() {
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE);
+        let (lz, prev_occ) = ::lempel_ziv_77(data.as_ref(), &::profile::Profiler::new()).unwrap();
+        let sizes = (1..10).map(|level| {
+            let level = Level::new(level).unwrap();
+            let container = super::Container::with_documents(data.as_ref(), lz.as_ref(), prev_occ.as_ref(), &[data.len()], level);
+            assert_eq!(container.decompress().unwrap(), data);
+            assert!(container.verify(Some(data.as_ref())).is_ok());
+            assert_eq!(container.extract(1000, 5000).unwrap().as_ref(), &data[1000..6000]);
+            let mut bytes = Vec::new();
+            container.write_to(&mut bytes).unwrap();
+            bytes.len()
+        }).collect::<Vec<usize>>();
+        assert_eq!(super::Container::new(data.as_ref(), lz.as_ref(), prev_occ.as_ref()).level(), Some(Level::default().level));
+        assert!(sizes.windows(2).all(|w| w[1] <= w[0]), "{:?}", sizes);
+    }
+
+    #[test]
+    fn levels_never_grow_test() {
+        // Source text twice over: short copies in the first half, and copies from far back in the second.
+        let source = include_bytes!("container.rs");
+        let data = [source.as_ref(), source.as_ref()].concat();
+        let (lz, prev_occ) = ::lempel_ziv_77(data.as_slice(), &::profile::Profiler::new()).unwrap();
+        let sizes = (1..10).map(|level| {
+            let container = super::Container::with_documents(data.as_ref(), lz.as_ref(), prev_occ.as_ref(), &[data.len()], Level::new(level).unwrap());
+            let mut bytes = Vec::new();
+            container.write_to(&mut bytes).unwrap();
+            assert!(bytes.len() < data.len(), "level {} wrote {} bytes for {}", level, bytes.len(), data.len());
+            // Nothing from the second half should take more than its own phrases' worth of copies.
+            assert!(container.payload_len() < source.len(), "level {}", level);
+            bytes.len()
+        }).collect::<Vec<usize>>();
+        assert!(sizes.windows(2).all(|w| w[1] <= w[0]), "{:?}", sizes);
+    }
+
+    #[bench]
+    fn extract_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_repetitive_slice(utils::BENCH_SIZE);
+        let container = compress(data.as_ref());
+        let mut rng = rand::thread_rng();
+        bencher.iter(|| {
+            let start = rng.gen_range(0, data.len() - 4096);
+            container.extract(start, 4096).unwrap()
+        });
+    }
+}
@@ -0,0 +1,198 @@
+use rayon::prelude::*;
+
+// Explanation:
+// - Corpora:
+//     Random bytes are the worst case for us (almost every phrase is a literal or a copy of 2 or 3 bytes),
+// so benchmarking only on them says little about real inputs. These cover the other shapes that matter:
+//     LowEntropy:      independent bytes from a skewed distribution (about 2 bits each), so phrases are short
+//                      but mostly copies.
+//     Fibonacci:       the Fibonacci word (s_0 = "a", s_1 = "ab", s_n = s_(n-1) s_(n-2)), the classic worst
+//                      case for suffix sorting and about as repetitive as a string can be (O(log n) phrases).
+//     RepeatedBlocks:  one random block over and over with a few bytes changed in every copy, like versioned
+//                      files or VM images.
+//     Dna:             A, C, G and T, where a stretch is either new or a mutated copy of an earlier one (some
+//                      reverse complemented), roughly like a genome's repeats.
+//
+// - Randomness:
+//     rand is only a test dependency, so this has its own xorshift64* generator. Everything is seeded so a
+// corpus is the same from run to run. The corpora with independent parts are generated in parallel chunks of
+// FILL_CHUNK_LEN, each with its own generator seeded from the chunk number. The chunk length can't depend on
+// the number of threads, or the same seed would give a different corpus on every machine.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corpus {
+    Random,
+    LowEntropy,
+    Fibonacci,
+    RepeatedBlocks,
+    Dna
+}
+
+pub const ALL_CORPORA: &[Corpus] = &[Corpus::Random, Corpus::LowEntropy, Corpus::Fibonacci, Corpus::RepeatedBlocks, Corpus::Dna];
+
+const BLOCK_LEN: usize = 4096;
+const BLOCK_MUTATIONS: usize = 16;
+const DNA_SEGMENT_LEN: usize = 1024;
+const DNA_MUTATION_RATE: u64 = 50;
+const FILL_CHUNK_LEN: usize = 1 << 20;
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        // xorshift is stuck at 0 forever, and close seeds would start out close, so scramble it first.
+        XorShift(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    // Close enough to uniform for n much smaller than 2^64.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn fill_chunks<F>(len: usize, seed: u64, f: F) -> Box<[u8]> where F: Fn(&mut XorShift) -> u8 + Sync {
+    let mut data = vec![0u8; len].into_boxed_slice();
+    data.par_chunks_mut(FILL_CHUNK_LEN).enumerate().for_each(|(chunk, out)| {
+        let mut rng = XorShift::new(seed ^ (chunk as u64).wrapping_mul(0xff51afd7ed558ccd));
+        out.iter_mut().for_each(|byte| *byte = f(&mut rng));
+    });
+    data
+}
+
+fn fibonacci(len: usize) -> Box<[u8]> {
+    // Every Fibonacci word is a prefix of the next, and the one before is a prefix of it, so appending the
+    // start of the word to itself gives the next one.
+    let mut word = b"ab".to_vec();
+    let mut previous_len = 1;
+    while word.len() < len {
+        let word_len = word.len();
+        for i in 0..previous_len {
+            let byte = word[i];
+            word.push(byte);
+        }
+        previous_len = word_len;
+    }
+    word.truncate(len);
+    word.into_boxed_slice()
+}
+
+fn repeated_blocks(len: usize, seed: u64) -> Box<[u8]> {
+    let mut rng = XorShift::new(seed);
+    let block = (0..BLOCK_LEN).map(|_| rng.next() as u8).collect::<Vec<u8>>();
+    let mut data = vec![0u8; len].into_boxed_slice();
+    data.par_chunks_mut(BLOCK_LEN).enumerate().for_each(|(copy, out)| {
+        let mut rng = XorShift::new(seed ^ (copy as u64 + 1).wrapping_mul(0xff51afd7ed558ccd));
+        out.copy_from_slice(&block[..out.len()]);
+        for _ in 0..BLOCK_MUTATIONS {
+            let pos = rng.below(out.len());
+            out[pos] = rng.next() as u8;
+        }
+    });
+    data
+}
+
+fn dna(len: usize, seed: u64) -> Box<[u8]> {
+    const BASES: &[u8; 4] = b"ACGT";
+    let complement = |base: u8| match base { b'A' => b'T', b'T' => b'A', b'C' => b'G', _ => b'C' };
+    let mut rng = XorShift::new(seed);
+    let mut data = Vec::with_capacity(len + DNA_SEGMENT_LEN);
+    while data.len() < len {
+        let segment_len = DNA_SEGMENT_LEN / 2 + rng.below(DNA_SEGMENT_LEN);
+        if data.len() < segment_len || rng.below(2) == 0 {
+            for _ in 0..segment_len {
+                data.push(BASES[rng.below(4)]);
+            }
+        } else {
+            let source = rng.below(data.len() - segment_len + 1);
+            let reverse_complement = rng.below(4) == 0;
+            for i in 0..segment_len {
+                let base = if reverse_complement { complement(data[source + segment_len - 1 - i]) } else { data[source + i] };
+                let mutated = if rng.next().is_multiple_of(DNA_MUTATION_RATE) { BASES[rng.below(4)] } else { base };
+                data.push(mutated);
+            }
+        }
+    }
+    data.truncate(len);
+    data.into_boxed_slice()
+}
+
+impl Corpus {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Corpus::Random => "random",
+            Corpus::LowEntropy => "low-entropy",
+            Corpus::Fibonacci => "fibonacci",
+            Corpus::RepeatedBlocks => "repeated-blocks",
+            Corpus::Dna => "dna"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Corpus> {
+        ALL_CORPORA.iter().cloned().find(|corpus| corpus.name() == name)
+    }
+
+    pub fn generate(&self, len: usize, seed: u64) -> Box<[u8]> {
+        match *self {
+            Corpus::Random => fill_chunks(len, seed, |rng| rng.next() as u8),
+            // Trailing zeroes of a random number are geometric: 'a' half the time, 'b' a quarter, ...
+            Corpus::LowEntropy => fill_chunks(len, seed, |rng| b'a' + (rng.next().trailing_zeros() as u8).min(15)),
+            Corpus::Fibonacci => fibonacci(len),
+            Corpus::RepeatedBlocks => repeated_blocks(len, seed),
+            Corpus::Dna => dna(len, seed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rayon;
+    use super::{Corpus, ALL_CORPORA};
+
+    #[test]
+    fn generate_test() {
+        for &corpus in ALL_CORPORA.iter() {
+            for &len in [0, 1, 1000, 100000].iter() {
+                let data = corpus.generate(len, 7);
+                assert_eq!(data.len(), len);
+                assert_eq!(corpus.generate(len, 7), data);
+            }
+            assert_eq!(Corpus::from_name(corpus.name()), Some(corpus));
+        }
+        assert!(Corpus::from_name("lorem-ipsum").is_none());
+        assert!(Corpus::Random.generate(1000, 1) != Corpus::Random.generate(1000, 2));
+    }
+
+    #[test]
+    fn same_on_any_thread_count_test() {
+        let len = 3 * super::FILL_CHUNK_LEN + 5;
+        let generate = |threads: usize, corpus: Corpus| {
+            rayon::Configuration::new().num_threads(threads).build().unwrap().install(|| corpus.generate(len, 11))
+        };
+        for &corpus in [Corpus::Random, Corpus::LowEntropy].iter() {
+            assert_eq!(generate(1, corpus), generate(8, corpus));
+        }
+    }
+
+    #[test]
+    fn fibonacci_test() {
+        assert_eq!(Corpus::Fibonacci.generate(13, 0).as_ref(), b"abaababaabaab".as_ref());
+    }
+
+    #[test]
+    fn shapes_test() {
+        let dna = Corpus::Dna.generate(100000, 3);
+        assert!(dna.iter().all(|&base| b"ACGT".contains(&base)));
+        let low_entropy = Corpus::LowEntropy.generate(100000, 3);
+        assert!(low_entropy.iter().filter(|&&byte| byte == b'a').count() > 40000);
+        let blocks = Corpus::RepeatedBlocks.generate(100000, 3);
+        let differences = (0..super::BLOCK_LEN).filter(|&i| blocks[i] != blocks[super::BLOCK_LEN + i]).count();
+        assert!(differences <= 2 * super::BLOCK_MUTATIONS);
+    }
+}
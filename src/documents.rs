@@ -0,0 +1,141 @@
+use rayon::prelude::*;
+
+// Explanation:
+// - Separators:
+//     To factorize a collection of documents at once we concatenate them, putting a separator after each one.
+// If the separators were all the same symbol, lpf_3 would happily find matches running from the end of one
+// document into the next. Instead every document gets its own separator, symbols 0..n_documents, and the
+// bytes are shifted up past them. A separator occurs exactly once, so its lcp with every other suffix is 0,
+// which means no match can contain one: every phrase stays inside its document, and every separator ends up
+// a literal phrase on its own.
+//
+// - Back to bytes:
+//     The separators aren't part of the data, so once we have the factorization we drop their phrases and
+// shift every position left by the number of separators before it. Copies never include a separator either,
+// so a copy and its source shift by their own documents' amounts and stay intact.
+
+// Where each document starts in the concatenation, with the total length on the end.
+pub fn document_starts(lens: &[usize], separator_len: usize) -> Box<[usize]> {
+    let mut start = 0;
+    let mut starts = lens.iter().map(|&len| {
+        let document_start = start;
+        start += len + separator_len;
+        document_start
+    }).collect::<Vec<usize>>();
+    starts.push(start);
+    starts.into_boxed_slice()
+}
+
+// Which document position pos is in, given document_starts. Empty documents start in the same place as
+// the next one, so this is the last document starting at or before pos.
+pub fn document_of(starts: &[usize], pos: usize) -> usize {
+    let (mut lo, mut hi) = (0, starts.len());
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if starts[mid] <= pos {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+pub fn to_symbols(documents: &[&[u8]]) -> Box<[u32]> {
+    let n_documents = documents.len() as u32;
+    let lens = documents.iter().map(|document| document.len()).collect::<Vec<usize>>();
+    let starts = document_starts(lens.as_ref(), 1);
+    let mut symbols = vec![0u32; *starts.last().unwrap()].into_boxed_slice();
+    {
+        let mut slices = Vec::with_capacity(documents.len());
+        let mut rest = symbols.as_mut();
+        for &len in lens.iter() {
+            let (document, after) = { rest }.split_at_mut(len + 1);
+            slices.push(document);
+            rest = after;
+        }
+        slices.into_par_iter().zip(documents.par_iter()).enumerate().for_each(|(idx, (out, document))| {
+            out.iter_mut().zip(document.iter()).for_each(|(symbol, &byte)| *symbol = byte as u32 + n_documents);
+            out[document.len()] = idx as u32;
+        });
+    }
+    symbols
+}
+
+// Takes a factorization of to_symbols(documents) to one of the documents' bytes, one after another.
+pub fn to_byte_space(lens: &[usize], lz: &[usize], prev_occ: &[isize]) -> (Box<[usize]>, Box<[isize]>) {
+    let symbol_starts = document_starts(lens, 1);
+    let byte_starts = document_starts(lens, 0);
+    let n_symbols = *symbol_starts.last().unwrap();
+    let n_bytes = *byte_starts.last().unwrap();
+
+    let to_byte = |pos: usize| pos - document_of(symbol_starts.as_ref(), pos);
+    let is_separator = |pos: usize| {
+        let document = document_of(symbol_starts.as_ref(), pos);
+        pos == symbol_starts[document] + lens[document]
+    };
+
+    let byte_lz = lz.par_iter()
+        .filter(|&&pos| pos == n_symbols || !is_separator(pos))
+        .map(|&pos| if pos == n_symbols { n_bytes } else { to_byte(pos) })
+        .collect::<Vec<usize>>().into_boxed_slice();
+
+    let byte_prev_occ = (0..n_bytes).into_par_iter().map(|byte_pos| {
+        let pos = byte_pos + document_of(byte_starts.as_ref(), byte_pos);
+        match prev_occ[pos] {
+            -1 => -1,
+            source => to_byte(source as usize) as isize
+        }
+    }).collect::<Vec<isize>>().into_boxed_slice();
+
+    (byte_lz, byte_prev_occ)
+}
+
+#[cfg(test)]
+mod test {
+    use utils;
+    use rand;
+    use rand::Rng;
+
+    fn random_documents() -> Vec<Box<[u8]>> {
+        // The same document over and over (and some empty ones), so there's plenty to match across.
+        let mut rng = rand::thread_rng();
+        let base = utils::random_slice_from_alphabet(512, 4);
+        (0..64).map(|_| {
+            let start = rng.gen_range(0, base.len());
+            let end = rng.gen_range(start, base.len() + 1);
+            base[start..end].to_vec().into_boxed_slice()
+        }).collect()
+    }
+
+    #[test]
+    fn to_symbols_test() {
+        let documents = vec![b"ab".as_ref(), b"".as_ref(), b"c".as_ref()];
+        let symbols = super::to_symbols(documents.as_ref());
+        assert_eq!(symbols.as_ref(), &[b'a' as u32 + 3, b'b' as u32 + 3, 0, 1, b'c' as u32 + 3, 2]);
+    }
+
+    #[test]
+    fn phrases_stay_in_documents_test() {
+        let documents = random_documents();
+        let slices = documents.iter().map(|document| document.as_ref()).collect::<Vec<&[u8]>>();
+        let lens = documents.iter().map(|document| document.len()).collect::<Vec<usize>>();
+        let (lz, prev_occ) = ::lempel_ziv_77(super::to_symbols(slices.as_ref()).as_ref(), &::profile::Profiler::new()).unwrap();
+        let (byte_lz, byte_prev_occ) = super::to_byte_space(lens.as_ref(), lz.as_ref(), prev_occ.as_ref());
+
+        let data = slices.concat();
+        let starts = super::document_starts(lens.as_ref(), 0);
+        byte_lz.windows(2).for_each(|phrase| {
+            let (start, end) = (phrase[0], phrase[1]);
+            let document = super::document_of(starts.as_ref(), start);
+            assert!(end <= starts[document + 1]);
+            if byte_prev_occ[start] >= 0 {
+                let source = byte_prev_occ[start] as usize;
+                let source_document = super::document_of(starts.as_ref(), source);
+                assert!(source + end - start <= starts[source_document + 1]);
+                assert_eq!(&data[source..source + end - start], &data[start..end]);
+            }
+        });
+        assert_eq!(*byte_lz.last().unwrap(), data.len());
+    }
+}
@@ -0,0 +1,115 @@
+use std;
+use std::fmt;
+
+// Explanation:
+// - Kinds:
+//     Io is anything the OS turned down (missing files, full disks). SuffixArray is saxx failing, with the
+// code it gave back. Format is input that isn't what we were asked to treat it as: a file that isn't one of
+// ours, an argument out of range, bytes that don't split into symbols. Corrupt is one of our own files that
+// doesn't decode, or decodes to something that doesn't match its checksums. ThreadPool is rayon not giving
+// us the threads we asked for. Mismatch is two ways of working something out that should agree not agreeing,
+// which is a bug of ours rather than anything wrong with the input.
+//
+// - Exit codes:
+//     clap already exits with 1 when the arguments don't parse, so ours start at 2 and every kind gets its
+// own, so scripts can tell a corrupt file from a missing one without reading the message.
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    SuffixArray(i8),
+    Format(String),
+    Corrupt(String),
+    ThreadPool(String),
+    Mismatch(String)
+}
+
+pub const EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    Success
+    1    Invalid arguments
+    2    I/O error
+    3    Suffix array construction failed
+    4    Input isn't in the expected format
+    5    Compressed data is corrupt
+    6    Couldn't start the thread pool
+    7    Internal results disagree (a bug)";
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Io(_) => 2,
+            Error::SuffixArray(_) => 3,
+            Error::Format(_) => 4,
+            Error::Corrupt(_) => 5,
+            Error::ThreadPool(_) => 6,
+            Error::Mismatch(_) => 7
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::SuffixArray(code) => write!(f, "Suffix array generation failed with code {}.", code),
+            Error::Format(ref message) | Error::Corrupt(ref message) | Error::ThreadPool(ref message) |
+            Error::Mismatch(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::SuffixArray(_) => "suffix array construction failed",
+            Error::Format(_) => "input isn't in the expected format",
+            Error::Corrupt(_) => "compressed data is corrupt",
+            Error::ThreadPool(_) => "couldn't start the thread pool",
+            Error::Mismatch(_) => "internal results disagree"
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(err: std::num::ParseIntError) -> Error {
+        Error::Format(format!("Expected a number: {}.", err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std;
+    use super::Error;
+
+    #[test]
+    fn exit_codes_test() {
+        let errors = [
+            Error::from(std::io::Error::new(std::io::ErrorKind::NotFound, "missing")),
+            Error::SuffixArray(-2),
+            Error::from("x".parse::<usize>().unwrap_err()),
+            Error::Corrupt("corrupt".to_owned()),
+            Error::ThreadPool("no threads".to_owned()),
+            Error::Mismatch("mismatch".to_owned())
+        ];
+        let mut codes = errors.iter().map(|err| err.exit_code()).collect::<Vec<i32>>();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|&code| code > 1));
+        assert_eq!(errors[1].to_string(), "Suffix array generation failed with code -2.");
+    }
+}
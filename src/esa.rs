@@ -0,0 +1,267 @@
+// The CLI doesn't traverse suffix trees yet, this module is for library users and the tests.
+#![allow(dead_code)]
+
+use std;
+use rayon::prelude::*;
+use lcp;
+use cartesian_tree::{LcpInterval, LcpIntervalTree};
+
+// Explanation:
+// - Enhanced suffix array:
+//     The suffix array together with its lcp array and a child table, which is enough to simulate any
+// bottom-up traversal of the suffix tree (Abouelhoda, Kurtz and Ohlebusch 2004). Our child table is the
+// LcpIntervalTree built from the Cartesian tree of the lcp array.
+//
+// - bottom_up:
+//     Calls f on every lcp-interval once all of its child intervals are done, handing it their results
+// (left to right) and the leaf children, i.e. the suffix array positions in the interval which aren't in
+// any child interval. Subtrees which don't contain each other are independent, so we go level by level
+// from the deepest one, and everything on a level runs at once. Going by levels instead of recursing keeps
+// the stack flat on inputs like "aaaa...", where the tree is a path as long as the input.
+//
+// - Maximal repeats:
+//     A string which occurs at least twice and can't be extended to the left or right without losing an
+// occurrence. Every lcp-interval with lcp > 0 is a repeat which can't be extended to the right (that's what
+// makes it an interval), so we only need to check the left: the suffixes in the interval must not all be
+// preceded by the same character. A suffix starting at position 0 isn't preceded by anything, which counts
+// as a different character from everything.
+//
+// - Supermaximal repeats:
+//     Maximal repeats which aren't contained in any other maximal repeat. Those are the intervals with no
+// child intervals where every suffix is preceded by a different character.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LeftContext {
+    Unique(u8),
+    Diverse
+}
+
+pub struct EnhancedSuffixArray<'a> {
+    pub data: &'a [u8],
+    pub suffix_array: Box<[usize]>,
+    pub lcp: Box<[usize]>,
+    pub child_table: LcpIntervalTree
+}
+
+impl<'a> EnhancedSuffixArray<'a> {
+    pub fn new(data: &'a [u8], suffix_array: Box<[usize]>) -> EnhancedSuffixArray<'a> {
+        let lcp = lcp::lcp_array(data, suffix_array.as_ref());
+        let child_table = LcpIntervalTree::new(lcp.as_ref());
+        EnhancedSuffixArray {
+            data,
+            suffix_array,
+            lcp,
+            child_table
+        }
+    }
+
+    // Text positions of every occurrence of the interval's prefix, in suffix array order.
+    pub fn positions(&self, interval: &LcpInterval) -> &[usize] {
+        &self.suffix_array[interval.left..interval.right + 1]
+    }
+
+    // The common prefix of the interval.
+    pub fn prefix(&self, interval: &LcpInterval) -> &[u8] {
+        let start = self.suffix_array[interval.left];
+        &self.data[start..start + interval.lcp]
+    }
+
+    pub fn leaves(&self, node: usize) -> Vec<usize> {
+        let interval = self.child_table.interval(node);
+        let mut leaves = Vec::new();
+        let next = self.child_table.children(node).iter().fold(interval.left, |next, &child| {
+            let child_interval = self.child_table.interval(child);
+            leaves.extend(next..child_interval.left);
+            child_interval.right + 1
+        });
+        leaves.extend(next..interval.right + 1);
+        leaves
+    }
+
+    fn levels(&self) -> Vec<Vec<usize>> {
+        let mut levels = Vec::<Vec<usize>>::new();
+        if let Some(root) = self.child_table.root() {
+            levels.push(vec![root]);
+            loop {
+                let next_level = levels.last().unwrap().par_iter()
+                    .flat_map(|&node| self.child_table.children(node).to_vec())
+                    .collect::<Vec<usize>>();
+                if next_level.is_empty() {
+                    break;
+                }
+                levels.push(next_level);
+            }
+        }
+        levels
+    }
+
+    pub fn bottom_up<R, F>(&self, f: F) -> Box<[R]>
+        where R: Send + Sync,
+              F: Fn(&LcpInterval, &[&R], &[usize]) -> R + Sync {
+        let mut results = (0..self.child_table.len()).map(|_| None).collect::<Vec<Option<R>>>();
+
+        for level in self.levels().iter().rev() {
+            let level_results = {
+                let results = &results;
+                level.par_iter().map(|&node| {
+                    let child_results = self.child_table.children(node).iter()
+                        .map(|&child| results[child].as_ref().unwrap())
+                        .collect::<Vec<&R>>();
+                    f(self.child_table.interval(node), child_results.as_ref(), self.leaves(node).as_ref())
+                }).collect::<Vec<R>>()
+            };
+            level.iter().zip(level_results).for_each(|(&node, result)| {
+                results[node] = Some(result);
+            });
+        }
+
+        results.into_iter().map(|result| result.unwrap()).collect::<Vec<R>>().into_boxed_slice()
+    }
+
+    #[inline]
+    fn left_context(&self, sa_idx: usize) -> LeftContext {
+        match self.suffix_array[sa_idx] {
+            0 => LeftContext::Diverse,
+            pos => LeftContext::Unique(self.data[pos - 1])
+        }
+    }
+
+    pub fn maximal_repeats(&self) -> Vec<LcpInterval> {
+        let contexts = self.bottom_up(|_interval, child_contexts, leaves| {
+            child_contexts.iter().map(|&&context| context)
+                .chain(leaves.iter().map(|&leaf| self.left_context(leaf)))
+                .fold(None, |acc, context| {
+                    match acc {
+                        None => Some(context),
+                        Some(acc_context) if acc_context == context => Some(context),
+                        _ => Some(LeftContext::Diverse)
+                    }
+                }).unwrap()
+        });
+
+        self.child_table.intervals.par_iter().zip(contexts.par_iter())
+            .filter(|&(interval, &context)| interval.lcp > 0 && context == LeftContext::Diverse)
+            .map(|(interval, _)| *interval)
+            .collect()
+    }
+
+    pub fn supermaximal_repeats(&self) -> Vec<LcpInterval> {
+        (0..self.child_table.len()).into_par_iter()
+            .filter(|&node| {
+                let interval = self.child_table.interval(node);
+                if interval.lcp == 0 || !self.child_table.children(node).is_empty() {
+                    return false;
+                }
+                let mut seen = [false; 256];
+                (interval.left..interval.right + 1).all(|sa_idx| {
+                    match self.left_context(sa_idx) {
+                        LeftContext::Diverse => true,
+                        LeftContext::Unique(c) => !std::mem::replace(&mut seen[c as usize], true)
+                    }
+                })
+            })
+            .map(|node| *self.child_table.interval(node))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+    use utils;
+    use saxx;
+    use test;
+
+    fn suffix_array(data: &[u8]) -> Box<[usize]> {
+        let esa = saxx::Esaxx::<i64>::esaxx(data).unwrap();
+        esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>().into_boxed_slice()
+    }
+
+    fn naive_maximal_repeats(data: &[u8]) -> HashSet<Vec<u8>> {
+        let mut occurrences = HashMap::<&[u8], Vec<usize>>::new();
+        for start in 0..data.len() {
+            for end in start + 1..data.len() + 1 {
+                occurrences.entry(&data[start..end]).or_default().push(start);
+            }
+        }
+        occurrences.iter()
+            .filter(|&(repeat, positions)| {
+                let left_diverse = positions.iter().any(|&p| p == 0 || data[p - 1] != data[positions[0] - 1]);
+                let right_diverse = positions.iter().any(|&p| {
+                    let end = p + repeat.len();
+                    end == data.len() || data[end] != data[positions[0] + repeat.len()]
+                });
+                positions.len() > 1 && left_diverse && right_diverse
+            })
+            .map(|(repeat, _)| repeat.to_vec())
+            .collect()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn maximal_repeats_test() {
+        for _ in 0..32 {
+            let data = utils::random_slice_from_alphabet(64, 3);
+            let esa = super::EnhancedSuffixArray::new(data.as_ref(), suffix_array(data.as_ref()));
+
+            let repeats = esa.maximal_repeats().iter()
+                .map(|interval| esa.prefix(interval).to_vec())
+                .collect::<HashSet<Vec<u8>>>();
+            let naive_repeats = naive_maximal_repeats(data.as_ref());
+            assert_eq!(repeats, naive_repeats);
+
+            let supermaximal = esa.supermaximal_repeats().iter()
+                .map(|interval| esa.prefix(interval).to_vec())
+                .collect::<HashSet<Vec<u8>>>();
+            let naive_supermaximal = naive_repeats.iter()
+                .filter(|&repeat| !naive_repeats.iter().any(|other| other.len() > repeat.len() && contains(other, repeat)))
+                .cloned()
+                .collect::<HashSet<Vec<u8>>>();
+            assert_eq!(supermaximal, naive_supermaximal);
+        }
+    }
+
+    #[test]
+    fn bottom_up_test() {
+        // Every suffix ends up in exactly one leaf list, and the sizes add up on the way up.
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE, 4);
+        let esa = super::EnhancedSuffixArray::new(data.as_ref(), suffix_array(data.as_ref()));
+        let sizes = esa.bottom_up(|interval, child_sizes, leaves| {
+            let size = child_sizes.iter().map(|&&size| size).sum::<usize>() + leaves.len();
+            assert_eq!(size, interval.right - interval.left + 1);
+            size
+        });
+        assert_eq!(sizes[esa.child_table.root().unwrap()], data.len());
+    }
+
+    #[test]
+    fn deep_tree_test() {
+        let data = vec![0u8; utils::DEFAULT_TEST_SIZE];
+        let esa = super::EnhancedSuffixArray::new(data.as_ref(), suffix_array(data.as_ref()));
+        assert_eq!(esa.maximal_repeats().len(), data.len() - 1);
+        assert_eq!(esa.supermaximal_repeats().len(), 1);
+    }
+
+    #[test]
+    fn maximal_repeats_fixed_test() {
+        let data = b"xabcyabcwabcyz";
+        let esa = super::EnhancedSuffixArray::new(data.as_ref(), suffix_array(data.as_ref()));
+        let mut repeats = esa.maximal_repeats().iter().map(|interval| esa.prefix(interval).to_vec()).collect::<Vec<Vec<u8>>>();
+        repeats.sort();
+        assert_eq!(repeats, vec![b"abc".to_vec(), b"abcy".to_vec()]);
+        let supermaximal = esa.supermaximal_repeats().iter().map(|interval| esa.prefix(interval).to_vec()).collect::<Vec<Vec<u8>>>();
+        assert_eq!(supermaximal, vec![b"abcy".to_vec()]);
+    }
+
+    #[bench]
+    fn maximal_repeats_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_slice_from_alphabet(utils::BENCH_SIZE, 4);
+        let esa = super::EnhancedSuffixArray::new(data.as_ref(), suffix_array(data.as_ref()));
+        bencher.iter(|| {
+            esa.maximal_repeats()
+        });
+    }
+}
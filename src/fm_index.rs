@@ -0,0 +1,504 @@
+use std;
+use std::io::{Read, Write};
+use utils::{read_u64, write_u64, read_bytes, read_varint, write_varint};
+use bwt;
+use error::Error;
+use rayon::prelude::*;
+
+// Explanation:
+// - FM-index:
+//     The bwt plus enough to take LF steps quickly: C[c] (the first row starting with c) and occurrence counts
+// of every byte sampled every OCC_SAMPLE_RATE positions of the bwt, so rank(c, i) is a lookup plus a short
+// scan. Rows are numbered like in bwt, with the sentinel row (primary) put back in.
+//
+// - Counting:
+//     Backward search. The rows starting with pattern[k..] are a range, and the rows starting with
+// c + pattern[k..] are the LF of the rows in that range whose last character is c, which (because LF keeps
+// the order of equal characters) is again a range: C[c] + rank(c, start) to C[c] + rank(c, end). So a pattern
+// of length m takes 2m ranks, no matter how long the text is.
+//
+// - Locating:
+//     Each row is one suffix, but we only keep the suffix array entry for rows whose text position is a
+// multiple of SA_SAMPLE_RATE. Those rows are marked in a bit vector, and the entries are stored in row order
+// so rank on the bit vector finds them. Any other row takes LF steps (each one moves a position back in the
+// text) until it lands on a sampled row, which takes fewer than SA_SAMPLE_RATE steps. An index read from a
+// file that takes more than that is corrupt, rather than something to keep walking (maybe forever) for.
+//
+// - On disk:
+//     Little endian, starting with FM_INDEX_MAGIC. Only the bwt, the sampled rows and their entries are
+// written; C and the occurrence counts are a single pass over the bwt, so they're rebuilt on load.
+//     The bwt of repetitive text is mostly long runs of the same byte, so it's written run-length coded: the
+// byte for a run of one, and the byte twice followed by a varint of the rest of the run for anything longer
+// (runs are as long as they go, so the next run never starts with the same byte). The entries only need
+// enough bits for the text length, so they're packed that tight. In memory everything is kept as is, so
+// rank stays a lookup and a short scan.
+//     That's what makes the index smaller than the text, when it is: the sampled rows are a bit per byte of
+// text and the entries about a bit more, so on text without long runs in its bwt (e.g. random bytes) the
+// index comes out a bit bigger than the text. SA_SAMPLE_RATE trades that against how long locate takes.
+//     Files written before the bwt was run-length coded start with FM_INDEX_RAW_MAGIC and are still read.
+
+const OCC_SAMPLE_RATE: usize = 1024;
+const SA_SAMPLE_RATE: usize = 32;
+const FM_INDEX_MAGIC: &[u8; 4] = b"FMI2";
+const FM_INDEX_RAW_MAGIC: &[u8; 4] = b"FMIX";
+
+fn corrupt() -> Error {
+    Error::Corrupt("FM-index is corrupt.".to_owned())
+}
+
+fn run_length_encode(bwt: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    while start < bwt.len() {
+        let c = bwt[start];
+        let run = bwt[start..].iter().take_while(|&&b| b == c).count();
+        out.push(c);
+        if run > 1 {
+            out.push(c);
+            write_varint(&mut out, run - 2);
+        }
+        start += run;
+    }
+    out
+}
+
+fn run_length_decode(bytes: &[u8], len: usize) -> Option<Box<[u8]>> {
+    // len isn't trusted until the runs add up to it.
+    let mut out = Vec::with_capacity(len.min(bytes.len()));
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let c = bytes[offset];
+        offset += 1;
+        let mut run = 1;
+        if bytes.get(offset) == Some(&c) {
+            offset += 1;
+            run = 2usize.checked_add(read_varint(bytes, &mut offset)?)?;
+        }
+        if run > len - out.len() {
+            return None;
+        }
+        out.extend(std::iter::repeat_n(c, run));
+    }
+    if out.len() == len {
+        Some(out.into_boxed_slice())
+    } else {
+        None
+    }
+}
+
+// Bits needed for every value up to max.
+fn bits_for(max: usize) -> usize {
+    (64 - (max as u64).leading_zeros() as usize).max(1)
+}
+
+fn pack(values: &[usize], bits: usize) -> Box<[u64]> {
+    let mut words = vec![0u64; (values.len() * bits).div_ceil(64)];
+    for (i, &value) in values.iter().enumerate() {
+        let bit = i * bits;
+        words[bit / 64] |= (value as u64) << (bit % 64);
+        if bit % 64 + bits > 64 {
+            words[bit / 64 + 1] |= (value as u64) >> (64 - bit % 64);
+        }
+    }
+    words.into_boxed_slice()
+}
+
+fn unpack(words: &[u64], bits: usize, len: usize) -> Box<[usize]> {
+    let mask = if bits == 64 { !0u64 } else { (1u64 << bits) - 1 };
+    (0..len).map(|i| {
+        let bit = i * bits;
+        let mut value = words[bit / 64] >> (bit % 64);
+        if bit % 64 + bits > 64 {
+            value |= words[bit / 64 + 1] << (64 - bit % 64);
+        }
+        (value & mask) as usize
+    }).collect::<Vec<usize>>().into_boxed_slice()
+}
+
+// Bits with O(1) rank.
+pub struct BitVector {
+    words: Box<[u64]>,
+    // Number of ones in the words before each word.
+    word_ranks: Box<[usize]>
+}
+
+impl BitVector {
+    pub fn from_fn<F: Fn(usize) -> bool + Sync>(len: usize, f: F) -> BitVector {
+        let words = (0..len.div_ceil(64)).into_par_iter().map(|word| {
+            (word * 64..len.min(word * 64 + 64)).fold(0u64, |acc, i| acc | ((f(i) as u64) << (i % 64)))
+        }).collect::<Vec<u64>>().into_boxed_slice();
+        BitVector::from_words(words)
+    }
+
+    fn from_words(words: Box<[u64]>) -> BitVector {
+        let mut total = 0;
+        let word_ranks = words.iter().map(|&word| {
+            let rank = total;
+            total += word.count_ones() as usize;
+            rank
+        }).collect::<Vec<usize>>().into_boxed_slice();
+        BitVector {
+            words,
+            word_ranks
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    // Number of ones in [0, i).
+    #[inline]
+    pub fn rank(&self, i: usize) -> usize {
+        let word = i / 64;
+        if i.is_multiple_of(64) {
+            if word == self.words.len() {
+                self.word_ranks.last().map_or(0, |&rank| rank + self.words[word - 1].count_ones() as usize)
+            } else {
+                self.word_ranks[word]
+            }
+        } else {
+            self.word_ranks[word] + (self.words[word] & ((1 << (i % 64)) - 1)).count_ones() as usize
+        }
+    }
+}
+
+pub struct FmIndex {
+    bwt: Box<[u8]>,
+    primary: usize,
+    c: Box<[usize]>,
+    occ: Box<[usize]>,
+    sampled_rows: BitVector,
+    sa_samples: Box<[usize]>
+}
+
+fn count_occurrences(bwt: &[u8]) -> (Box<[usize]>, Box<[usize]>) {
+    let block_counts = bwt.par_chunks(OCC_SAMPLE_RATE).map(|block| {
+        let mut counts = [0usize; 256];
+        block.iter().for_each(|&c| counts[c as usize] += 1);
+        counts
+    }).collect::<Vec<[usize; 256]>>();
+
+    let mut occ = Vec::<usize>::with_capacity((block_counts.len() + 1) * 256);
+    let mut totals = [0usize; 256];
+    for counts in block_counts.iter() {
+        occ.extend_from_slice(&totals[..]);
+        for c in 0..256 {
+            totals[c] += counts[c];
+        }
+    }
+    occ.extend_from_slice(&totals[..]);
+
+    let mut row = 1;
+    let c = totals.iter().map(|&count| {
+        let first_row = row;
+        row += count;
+        first_row
+    }).collect::<Vec<usize>>().into_boxed_slice();
+
+    (c, occ.into_boxed_slice())
+}
+
+impl FmIndex {
+    pub fn new(data: &[u8], suffix_array: &[usize]) -> FmIndex {
+        let (transform, primary) = bwt::bwt(data, suffix_array);
+        let text_pos = |row: usize| if row == 0 { data.len() } else { suffix_array[row - 1] };
+        let sampled_rows = BitVector::from_fn(data.len() + 1, |row| text_pos(row) % SA_SAMPLE_RATE == 0);
+        let sa_samples = (0..data.len() + 1).into_par_iter()
+            .filter(|&row| sampled_rows.get(row))
+            .map(text_pos)
+            .collect::<Vec<usize>>().into_boxed_slice();
+        let (c, occ) = count_occurrences(transform.as_ref());
+        FmIndex {
+            bwt: transform,
+            primary,
+            c,
+            occ,
+            sampled_rows,
+            sa_samples
+        }
+    }
+
+    // Length of the indexed text.
+    pub fn len(&self) -> usize {
+        self.bwt.len()
+    }
+
+    // Occurrences of c in the last column above row.
+    #[inline]
+    pub fn rank(&self, c: u8, row: usize) -> usize {
+        let idx = if row <= self.primary { row } else { row - 1 };
+        let block = idx / OCC_SAMPLE_RATE;
+        self.occ[block * 256 + c as usize] + self.bwt[block * OCC_SAMPLE_RATE..idx].iter().filter(|&&b| b == c).count()
+    }
+
+    #[inline]
+    fn lf(&self, row: usize) -> usize {
+        if row == self.primary {
+            0
+        } else {
+            let c = self.bwt[if row < self.primary { row } else { row - 1 }];
+            self.c[c as usize] + self.rank(c, row)
+        }
+    }
+
+    // The rows starting with the pattern, as a half open range.
+    pub fn range(&self, pattern: &[u8]) -> (usize, usize) {
+        // Row 0 starts with the sentinel, so it doesn't count as an occurrence of the empty pattern. It still
+        // has to be in the range to begin with though, since its last character is the last one in the text.
+        if pattern.is_empty() {
+            return (1, self.bwt.len() + 1);
+        }
+        let mut start = 0;
+        let mut end = self.bwt.len() + 1;
+        for &c in pattern.iter().rev() {
+            start = self.c[c as usize] + self.rank(c, start);
+            end = self.c[c as usize] + self.rank(c, end);
+            if start >= end {
+                return (start, start);
+            }
+        }
+        (start, end)
+    }
+
+    pub fn count(&self, pattern: &[u8]) -> usize {
+        let (start, end) = self.range(pattern);
+        end - start
+    }
+
+    // Text position of the suffix on a row.
+    pub fn text_position(&self, row: usize) -> Result<usize, Error> {
+        let mut row = row;
+        let mut steps = 0;
+        while !self.sampled_rows.get(row) {
+            if steps == SA_SAMPLE_RATE {
+                return Err(corrupt());
+            }
+            row = self.lf(row);
+            steps += 1;
+        }
+        Ok(self.sa_samples[self.sampled_rows.rank(row)] + steps)
+    }
+
+    // Every occurrence of the pattern, in order.
+    pub fn locate(&self, pattern: &[u8]) -> Result<Box<[usize]>, Error> {
+        let (start, end) = self.range(pattern);
+        let mut positions = (start..end).into_par_iter()
+            .map(|row| self.text_position(row))
+            .collect::<Result<Vec<usize>, Error>>()?;
+        positions.par_sort_unstable();
+        Ok(positions.into_boxed_slice())
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let runs = run_length_encode(self.bwt.as_ref());
+        let bits = bits_for(self.bwt.len());
+        writer.write_all(FM_INDEX_MAGIC)?;
+        write_u64(writer, self.bwt.len() as u64)?;
+        write_u64(writer, self.primary as u64)?;
+        write_u64(writer, SA_SAMPLE_RATE as u64)?;
+        write_u64(writer, runs.len() as u64)?;
+        writer.write_all(runs.as_ref())?;
+        for &word in self.sampled_rows.words.iter() {
+            write_u64(writer, word)?;
+        }
+        for &word in pack(self.sa_samples.as_ref(), bits).iter() {
+            write_u64(writer, word)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<FmIndex, Error> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let run_length_coded = if &magic == FM_INDEX_MAGIC {
+            true
+        } else if &magic == FM_INDEX_RAW_MAGIC {
+            false
+        } else {
+            return Err(Error::Format("Not an FM-index file.".to_owned()));
+        };
+        let len = read_u64(reader)? as usize;
+        let primary = read_u64(reader)? as usize;
+        if read_u64(reader)? as usize != SA_SAMPLE_RATE {
+            return Err(Error::Format("FM-index was written with a different sample rate.".to_owned()));
+        }
+        let transform = if run_length_coded {
+            let runs_len = read_u64(reader)?;
+            let runs = read_bytes(reader, runs_len)?.ok_or_else(corrupt)?;
+            run_length_decode(runs.as_ref(), len).ok_or_else(corrupt)?
+        } else {
+            read_bytes(reader, len as u64)?.ok_or_else(corrupt)?.into_boxed_slice()
+        };
+        let words = (0..(len + 1).div_ceil(64)).map(|_| read_u64(reader))
+            .collect::<std::io::Result<Vec<u64>>>()?.into_boxed_slice();
+        let sampled_rows = BitVector::from_words(words);
+        let sample_count = sampled_rows.rank(len + 1);
+        let sa_samples = if run_length_coded {
+            let bits = bits_for(len);
+            let words = (0..(sample_count * bits).div_ceil(64)).map(|_| read_u64(reader))
+                .collect::<std::io::Result<Vec<u64>>>()?;
+            unpack(words.as_ref(), bits, sample_count)
+        } else {
+            (0..sample_count).map(|_| read_u64(reader).map(|sample| sample as usize))
+                .collect::<std::io::Result<Vec<usize>>>()?.into_boxed_slice()
+        };
+        // Every text position that's a multiple of SA_SAMPLE_RATE is sampled, 0 (on the primary row) included,
+        // so LF always reaches a sampled row within SA_SAMPLE_RATE steps.
+        if primary > len || (len > 0 && primary == 0) || sample_count != len / SA_SAMPLE_RATE + 1 ||
+            sa_samples.iter().any(|&sample| sample > len || sample % SA_SAMPLE_RATE != 0) ||
+            !sampled_rows.get(primary) || sa_samples[sampled_rows.rank(primary)] != 0 {
+            return Err(corrupt());
+        }
+        let (c, occ) = count_occurrences(transform.as_ref());
+        Ok(FmIndex {
+            bwt: transform,
+            primary,
+            c,
+            occ,
+            sampled_rows,
+            sa_samples
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std;
+    use utils;
+    use saxx;
+    use search;
+    use rand;
+    use rand::Rng;
+    use test;
+
+    fn suffix_array(data: &[u8]) -> Box<[usize]> {
+        let esa = saxx::Esaxx::<i64>::esaxx(data).unwrap();
+        esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>().into_boxed_slice()
+    }
+
+    #[test]
+    fn bit_vector_test() {
+        let bits = utils::random_slice_from_alphabet(1000, 2);
+        let bit_vector = super::BitVector::from_fn(bits.len(), |i| bits[i] == 1);
+        let mut ones = 0;
+        for i in 0..bits.len() {
+            assert_eq!(bit_vector.rank(i), ones);
+            if bits[i] == 1 {
+                ones += 1;
+            }
+        }
+        assert_eq!(bit_vector.rank(bits.len()), ones);
+    }
+
+    #[test]
+    fn rank_test() {
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE, 4);
+        let fm_index = super::FmIndex::new(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        let mut counts = [0usize; 4];
+        for row in 0..data.len() + 1 {
+            for c in 0..4u8 {
+                assert_eq!(fm_index.rank(c, row), counts[c as usize]);
+            }
+            if row != fm_index.primary {
+                counts[fm_index.bwt[if row < fm_index.primary { row } else { row - 1 }] as usize] += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn locate_matches_search_test() {
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE);
+        let sa = suffix_array(data.as_ref());
+        let fm_index = super::FmIndex::new(data.as_ref(), sa.as_ref());
+        let searcher = search::SuffixArraySearch::new(data.as_ref(), sa.as_ref());
+
+        for row in 1..data.len() + 1 {
+            assert_eq!(fm_index.text_position(row).unwrap(), sa[row - 1]);
+        }
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..utils::DEFAULT_TEST_SAMPLE_SIZE {
+            let len = rng.gen_range(1, 16);
+            let start = rng.gen_range(0, data.len() - len);
+            let pattern = &data[start..start + len];
+            assert_eq!(fm_index.locate(pattern).unwrap(), searcher.locate(pattern));
+        }
+        assert_eq!(fm_index.count(b""), data.len());
+    }
+
+    #[test]
+    fn serialize_test() {
+        let data = b"the quick brown fox was quick.";
+        let fm_index = super::FmIndex::new(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        let mut bytes = Vec::new();
+        fm_index.write_to(&mut bytes).unwrap();
+        let read_index = super::FmIndex::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read_index.locate(b"quick").unwrap().as_ref(), &[4, 24]);
+        assert_eq!(read_index.count(b"faux"), 0);
+        // The last character is only reachable through the sentinel row.
+        assert_eq!(read_index.locate(b"k.").unwrap().as_ref(), &[28]);
+        assert!(super::FmIndex::read_from(&mut &bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn corrupt_test() {
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE);
+        let mut fm_index = super::FmIndex::new(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        let mut bytes = Vec::new();
+        fm_index.write_to(&mut bytes).unwrap();
+        let runs_len = utils::read_u64(&mut &bytes[28..36]).unwrap() as usize;
+
+        // A run count far past the end of the file.
+        let mut huge_runs = bytes.clone();
+        huge_runs[28..36].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x40]);
+        assert_eq!(super::FmIndex::read_from(&mut huge_runs.as_slice()).err().unwrap().exit_code(), 5);
+        // A sampled row missing.
+        let mut missing_sample = bytes.clone();
+        let word = (36 + runs_len..).step_by(8).find(|&offset| missing_sample[offset] != 0).unwrap();
+        missing_sample[word] &= missing_sample[word] - 1;
+        assert_eq!(super::FmIndex::read_from(&mut missing_sample.as_slice()).err().unwrap().exit_code(), 5);
+
+        // The right number of sampled rows, but not the right ones, takes too many steps to get to one.
+        let sampled = (1..data.len() + 1).find(|&row| row != fm_index.primary && fm_index.sampled_rows.get(row)).unwrap();
+        let unsampled = (1..data.len() + 1).find(|&row| !fm_index.sampled_rows.get(row)).unwrap();
+        let mut words = fm_index.sampled_rows.words.to_vec();
+        words[sampled / 64] &= !(1 << (sampled % 64));
+        words[unsampled / 64] |= 1 << (unsampled % 64);
+        fm_index.sampled_rows = super::BitVector::from_words(words.into_boxed_slice());
+        assert!(fm_index.locate(b"").is_err());
+    }
+
+    #[test]
+    fn compressed_on_disk_test() {
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE);
+        let fm_index = super::FmIndex::new(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        let mut bytes = Vec::new();
+        fm_index.write_to(&mut bytes).unwrap();
+        assert!(bytes.len() < data.len());
+        let read_index = super::FmIndex::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read_index.bwt, fm_index.bwt);
+        assert_eq!(read_index.sa_samples, fm_index.sa_samples);
+
+        let runs = [0u8, 0, 0, 7, 7, 1, 0, 0].iter().cloned().chain(std::iter::repeat_n(9, 300)).collect::<Vec<u8>>();
+        let encoded = super::run_length_encode(runs.as_ref());
+        assert_eq!(super::run_length_decode(encoded.as_ref(), runs.len()).unwrap().as_ref(), runs.as_slice());
+        assert!(super::run_length_decode(encoded.as_ref(), runs.len() - 1).is_none());
+
+        let values = (0..1000).map(|i| i * 7919 % 100003).collect::<Vec<usize>>();
+        let bits = super::bits_for(100003);
+        assert_eq!(super::unpack(super::pack(values.as_ref(), bits).as_ref(), bits, values.len()).as_ref(), values.as_slice());
+    }
+
+    #[bench]
+    fn locate_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_repetitive_slice(utils::BENCH_SIZE);
+        let fm_index = super::FmIndex::new(data.as_ref(), suffix_array(data.as_ref()).as_ref());
+        let mut rng = rand::thread_rng();
+        bencher.iter(|| {
+            let start = rng.gen_range(0, data.len() - 32);
+            fm_index.locate(&data[start..start + 32]).unwrap()
+        });
+    }
+}
@@ -0,0 +1,152 @@
+use rayon::prelude::*;
+use utils;
+
+// Explanation:
+// - lcp:
+//     lcp[i] is the length of the longest common prefix of the suffixes at suffix_array[i - 1] and
+// suffix_array[i], i.e. of each suffix with its lexicographic predecessor. lcp[0] has no predecessor
+// and is always 0.
+//
+// - phi:
+//     phi[k] is the text position of the suffix which comes just before the suffix at text position k
+// in the suffix array. In other words, phi[suffix_array[i]] = suffix_array[i - 1]. The suffix which is
+// first in the suffix array has no predecessor, so it gets data.len() as a marker instead.
+//
+// - plcp:
+//     The "permuted" lcp array. plcp[k] is the lcp of the suffix at text position k with phi[k], so
+// plcp[suffix_array[i]] = lcp[i]. The point of computing this one first is Kasai's observation: if the
+// suffix at k shares h characters with phi[k], then the suffix at k + 1 shares at least h - 1 characters
+// with phi[k + 1]. So walking the text in order we only ever have to compare characters past h - 1,
+// which keeps the total number of comparisons linear.
+//     The observation only relies on the previous element, so we can chop the text into chunks and
+// run the walk on every chunk at once, each one simply starting over from h = 0 at its first position.
+
+pub fn phi_array(suffix_array: &[usize]) -> Box<[usize]> {
+    let ar_len = suffix_array.len();
+    let phi = vec![0usize; ar_len].into_boxed_slice();
+
+    // Every position of phi is written exactly once, since suffix_array is a permutation.
+    suffix_array.par_iter().enumerate().for_each(|(i, &data_i)| {
+        let prev = if i == 0 {
+            ar_len
+        } else {
+            suffix_array[i - 1]
+        };
+        unsafe {
+            *(phi.as_ptr() as *mut usize).add(data_i) = prev;
+        }
+    });
+
+    phi
+}
+
+pub fn plcp_array<T: Eq + Sync>(data: &[T], suffix_array: &[usize]) -> Box<[usize]> {
+    let ar_len = data.len();
+
+    let mut plcp = phi_array(suffix_array);
+
+    if ar_len == 0 {
+        return plcp;
+    }
+
+    let size = utils::rayon_chunk_size(ar_len).max(1);
+
+    plcp.par_chunks_mut(size).enumerate().for_each(|(chunk_idx, plcp_chunk)| {
+        plcp_chunk.iter_mut().enumerate().fold(0usize, |prev_h, (el_idx, plcp_el)| {
+            let pos = chunk_idx * size + el_idx;
+            let prev_pos = *plcp_el;
+
+            if prev_pos == ar_len {
+                *plcp_el = 0;
+                return 0;
+            }
+
+            let min_h = prev_h.saturating_sub(1);
+            let h = min_h + get_lcp(&data[prev_pos + min_h..], &data[pos + min_h..]);
+            *plcp_el = h;
+            h
+        });
+    });
+
+    plcp
+}
+
+// Only the library modules and tests build lcp arrays so far, the CLI doesn't.
+#[allow(dead_code)]
+pub fn lcp_array<T: Eq + Sync>(data: &[T], suffix_array: &[usize]) -> Box<[usize]> {
+    let plcp = plcp_array(data, suffix_array);
+    suffix_array.par_iter().map(|&data_i| plcp[data_i]).collect::<Vec<usize>>().into_boxed_slice()
+}
+
+// Just iterates through the string until they're not equal, and counts how long the iteration lasted.
+#[inline(always)]
+pub fn get_lcp<T: Eq>(l: &[T], r: &[T]) -> usize {
+    l.iter().zip(r.iter()).take_while(|&(l_el, r_el)| l_el == r_el).count()
+}
+
+#[cfg(test)]
+mod test {
+    use utils;
+    use saxx;
+    use test;
+    use rayon::prelude::*;
+
+    fn suffix_array<T>(data: &[T]) -> Box<[usize]> where [T]: saxx::Esaxx<i64> {
+        let esa = saxx::Esaxx::<i64>::esaxx(data).unwrap();
+        esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>().into_boxed_slice()
+    }
+
+    fn validate_lcp<T: Eq + Sync>(data: &[T], sa: &[usize], lcp: &[usize]) {
+        assert_eq!(lcp.len(), sa.len());
+        if !lcp.is_empty() {
+            assert_eq!(lcp[0], 0);
+        }
+        (1..sa.len()).into_par_iter().for_each(|i| {
+            assert_eq!(super::get_lcp(&data[sa[i - 1]..], &data[sa[i]..]), lcp[i]);
+        });
+    }
+
+    #[test]
+    fn lcp_test() {
+        let data = utils::random_slice::<u8>(utils::DEFAULT_TEST_SIZE);
+        let sa = suffix_array(data.as_ref());
+        let lcp = super::lcp_array(data.as_ref(), sa.as_ref());
+        validate_lcp(data.as_ref(), sa.as_ref(), lcp.as_ref());
+    }
+
+    #[test]
+    fn lcp_small_alphabet_test() {
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE, 2);
+        let sa = suffix_array(data.as_ref());
+        let lcp = super::lcp_array(data.as_ref(), sa.as_ref());
+        validate_lcp(data.as_ref(), sa.as_ref(), lcp.as_ref());
+    }
+
+    #[test]
+    fn lcp_wide_alphabet_test() {
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE, 4).iter()
+            .map(|&c| c as u32 * 0x01000193)
+            .collect::<Vec<u32>>();
+        let sa = suffix_array(data.as_slice());
+        let lcp = super::lcp_array(data.as_slice(), sa.as_ref());
+        validate_lcp(data.as_slice(), sa.as_ref(), lcp.as_ref());
+    }
+
+    #[test]
+    fn lcp_fixed_test() {
+        let data = b"banana";
+        let sa = suffix_array(data.as_ref());
+        assert_eq!(sa.as_ref(), &[5, 3, 1, 0, 4, 2]);
+        let lcp = super::lcp_array(data.as_ref(), sa.as_ref());
+        assert_eq!(lcp.as_ref(), &[0, 1, 3, 0, 0, 2]);
+    }
+
+    #[bench]
+    fn lcp_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_slice::<u8>(utils::BENCH_SIZE);
+        let sa = suffix_array(data.as_ref());
+        bencher.iter(|| {
+            super::lcp_array(data.as_ref(), sa.as_ref());
+        });
+    }
+}
@@ -0,0 +1,96 @@
+use error::Error;
+
+// Explanation:
+// - Levels:
+//     Every level runs the same factorization, since that's where nearly all the time goes and it's already
+// the longest match at every step, so a level doesn't make compressing any faster. What a level picks is how
+// those phrases get written, which decides the size and how fast extract is:
+//     parse:          Greedy writes every copy it's allowed to as it is. Cheapest only takes a copy if it's
+//                     written in fewer bytes than the literals it replaces, and gives the positions inside one
+//                     it turns down another go with their own previous occurrence, so it's never bigger than
+//                     Greedy.
+//     coder:          Varint writes every literal as its own phrase (a 0 and the byte). LiteralRuns writes a
+//                     run of literals as one phrase (its length and the bytes), see container.rs. Every level
+//                     uses LiteralRuns, since a literal never costs much more than its byte that way; Varint is
+//                     what files from before levels are in.
+//     min_copy:       copies shorter than this are written as literals. Every copy is somewhere else extract
+//                     has to go and decode, and short ones are most of them while saving the least, so turning
+//                     them down makes extract a lot faster for a little size.
+//     block_phrases:  how many phrases of the factorization go in every indexed block, i.e. how far extract
+//                     may have to decode before it gets to where it's going. Every block costs an index entry.
+//     So level 1 extracts quickest and every level after it is no bigger than the one before: min_copy only
+// goes down and block_phrases only goes up.
+//
+// - Stored:
+//     The level goes in the header, so we can always say how a file was written, and so do its coder and
+// block size. Reading a file back only uses those and never goes through this table (see container.rs), so
+// it can change without breaking old files.
+
+pub const DEFAULT_LEVEL: u32 = 6;
+pub const MIN_LEVEL: u32 = 1;
+pub const MAX_LEVEL: u32 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parse {
+    Greedy,
+    Cheapest
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coder {
+    Varint,
+    LiteralRuns
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level {
+    pub level: u32,
+    pub parse: Parse,
+    pub coder: Coder,
+    pub min_copy: usize,
+    pub block_phrases: usize
+}
+
+impl Level {
+    pub fn new(level: u32) -> Result<Level, Error> {
+        let (parse, coder, min_copy, block_phrases) = match level {
+            1 => (Parse::Greedy, Coder::LiteralRuns, 12, 32),
+            2 => (Parse::Cheapest, Coder::LiteralRuns, 8, 32),
+            3 => (Parse::Cheapest, Coder::LiteralRuns, 8, 64),
+            4 => (Parse::Cheapest, Coder::LiteralRuns, 6, 64),
+            5 => (Parse::Cheapest, Coder::LiteralRuns, 5, 128),
+            6 => (Parse::Cheapest, Coder::LiteralRuns, 4, 128),
+            7 => (Parse::Cheapest, Coder::LiteralRuns, 3, 256),
+            8 => (Parse::Cheapest, Coder::LiteralRuns, 1, 512),
+            9 => (Parse::Cheapest, Coder::LiteralRuns, 1, 1024),
+            _ => return Err(Error::Format(format!("Level must be from {} to {}, not {}.", MIN_LEVEL, MAX_LEVEL, level)))
+        };
+        Ok(Level {
+            level,
+            parse,
+            coder,
+            min_copy,
+            block_phrases
+        })
+    }
+
+    pub fn default() -> Level {
+        Level::new(DEFAULT_LEVEL).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Level, Parse, Coder, MIN_LEVEL, MAX_LEVEL};
+
+    #[test]
+    fn levels_test() {
+        assert!(Level::new(MIN_LEVEL - 1).is_err());
+        assert!(Level::new(MAX_LEVEL + 1).is_err());
+        let levels = (MIN_LEVEL..MAX_LEVEL + 1).map(|level| Level::new(level).unwrap()).collect::<Vec<Level>>();
+        assert!(levels.windows(2).all(|w| w[0].block_phrases <= w[1].block_phrases));
+        assert!(levels.windows(2).all(|w| w[0].min_copy >= w[1].min_copy));
+        assert!(levels.windows(2).any(|w| w[0].min_copy > w[1].min_copy));
+        assert!(levels.iter().skip(1).all(|level| level.parse == Parse::Cheapest && level.coder == Coder::LiteralRuns));
+    }
+}
@@ -5,11 +5,21 @@ use std::convert::AsMut;
 use serial_suffix;
 use ansv;
 use std;
-
-// Just iterates through the string until they're not equal, and counts how long the iteration lasted.
-#[inline(always)]
-fn get_lcp(l: &[u8], r: &[u8]) -> usize {
-    l.iter().zip(r.iter()).take_while(|&(&l_el, &r_el)| l_el == r_el).count()
+use lcp::get_lcp;
+use rmq;
+use rmq::RangeMin;
+use profile::Profiler;
+
+// If we are considering a suffix at position k in the original string, rank[k] is that suffixes
+// position in the suffix array. In other (confusing) words: suffix_array[rank[k]] = k.
+pub fn rank_array(suffix_array: &[usize]) -> Box<[usize]> {
+    let rank_array = vec![0usize; suffix_array.len()].into_boxed_slice();
+    suffix_array.par_iter().enumerate().for_each(|(i, &data_i)| {
+        unsafe {
+            *(rank_array.as_ptr() as *mut usize).add(data_i) = i;
+        }
+    });
+    rank_array
 }
 
 // Explanation:
@@ -46,28 +56,30 @@ fn get_lcp(l: &[u8], r: &[u8]) -> usize {
 // suffix - 1, since its composed of the same letters shy of the first one, so that whatever the previous suffix's LPF suffix was,
 // that same suffix will produce LPF - 1 characters for us.. that may be slightly confusing.
 
-pub fn lpf_3(data: &[u8], suffix_array: &[usize], left_elements: &[isize], right_elements: &[isize]) -> (Box<[usize]>, Box<[isize]>) {
+// The CLI always profiles, so it calls lpf_3_profiled directly.
+#[allow(dead_code)]
+pub fn lpf_3<T: Eq + Sync>(data: &[T], suffix_array: &[usize], left_elements: &[isize], right_elements: &[isize]) -> (Box<[usize]>, Box<[isize]>) {
+    lpf_3_profiled(data, suffix_array, left_elements, right_elements, &Profiler::new())
+}
 
-    let ar_len = data.len();
+// The phase lpf_3_profiled records its chunks under, which callers should time it as too.
+pub const LPF_PHASE: &str = "LPF Array";
 
-    let mut longest_previous_factor = vec![0usize; ar_len].into_boxed_slice();
-    let mut prev_occ = vec![0isize; ar_len].into_boxed_slice();
+// lpf_3, recording every chunk with the profiler.
+pub fn lpf_3_profiled<T: Eq + Sync>(data: &[T], suffix_array: &[usize], left_elements: &[isize], right_elements: &[isize],
+                                    profiler: &Profiler) -> (Box<[usize]>, Box<[isize]>) {
 
+    let ar_len = data.len();
 
-    let mut rank_array = longest_previous_factor;
-    suffix_array.par_iter().enumerate().for_each(|(i, &data_i)| {
-        unsafe {
-            *(rank_array.as_ptr() as *mut usize).offset(data_i as isize) = i;
-        }
-    });
+    let mut prev_occ = vec![0isize; ar_len].into_boxed_slice();
 
-    let mut longest_previous_factor = rank_array;
+    let mut longest_previous_factor = rank_array(suffix_array);
 
-    let size = utils::rayon_chunk_size(ar_len);
+    let size = utils::rayon_chunk_size(ar_len).max(1);
 
     longest_previous_factor.as_mut().par_chunks_mut(size).zip(prev_occ.as_mut().par_chunks_mut(size))
         .enumerate()
-        .for_each(|(chunk_idx, (lpf_chunk, prev_occ_chunk))| {
+        .for_each(|(chunk_idx, (lpf_chunk, prev_occ_chunk))| profiler.chunk(LPF_PHASE, lpf_chunk.len(), || {
 
             lpf_chunk.iter_mut().zip(prev_occ_chunk.iter_mut()).enumerate() .fold((0, 0),
                  |(prev_llcp, prev_rlcp), (el_idx, (lpf_chunk_el, prev_occ_chunk_el))| {
@@ -106,7 +118,61 @@ pub fn lpf_3(data: &[u8], suffix_array: &[usize], left_elements: &[isize], right
 
                      (llcp, rlcp)
                  });
+        }));
+    (longest_previous_factor, prev_occ)
+}
+
+// Same result as lpf_3, but never looks at the string itself.
+//     The lcp of two suffixes is the minimum of the lcp array over the suffix array positions between
+// them (excluding the first). So instead of comparing llcp and rlcp character by character we ask an
+// rmq::MinTree over the lcp array for the minimum between our rank and left_elements[rank] (and likewise
+// right_elements[rank]). That costs O(log n) per suffix no matter how long the match is, which is what we
+// want on highly repetitive inputs where lpf_3's comparisons get long.
+//     Because nothing carries over from the previous suffix, every position is independent and we don't
+// need to chunk anything.
+//     The CLI still uses lpf_3, so for now this is only called by the tests and benchmarks.
+#[allow(dead_code)]
+pub fn lpf_from_lcp(suffix_array: &[usize], lcp: &[usize], left_elements: &[isize], right_elements: &[isize]) -> (Box<[usize]>, Box<[isize]>) {
+
+    let ar_len = suffix_array.len();
+
+    let mut prev_occ = vec![0isize; ar_len].into_boxed_slice();
+
+    let mut longest_previous_factor = rank_array(suffix_array);
+
+    let min_tree = rmq::MinTree::new(lcp);
+
+    longest_previous_factor.par_iter_mut().zip(prev_occ.par_iter_mut())
+        .for_each(|(lpf_el, prev_occ_el)| {
+            let rank = *lpf_el;
+
+            let left = left_elements[rank];
+            let right = right_elements[rank];
+
+            let llcp = if left != -1 {
+                min_tree.query(left as usize + 1, rank).0
+            } else {
+                0
+            };
+
+            let rlcp = if right != -1 {
+                min_tree.query(rank + 1, right as usize).0
+            } else {
+                0
+            };
+
+            if llcp == 0 && rlcp == 0 {
+                *prev_occ_el = -1;
+                *lpf_el = 1;
+            } else if llcp > rlcp {
+                *prev_occ_el = suffix_array[left as usize] as isize;
+                *lpf_el = llcp;
+            } else {
+                *prev_occ_el = suffix_array[right as usize] as isize;
+                *lpf_el = rlcp;
+            }
         });
+
     (longest_previous_factor, prev_occ)
 }
 
@@ -119,11 +185,12 @@ mod lpf_testing {
     use serial_suffix;
     use saxx;
     use ansv;
+    use lcp;
     use test;
 
     #[bench]
     fn lpf_calculator_bench(bencher: &mut test::Bencher) {
-        let data = utils::random_slice(utils::BENCH_SIZE);
+        let data = utils::random_slice::<u8>(utils::BENCH_SIZE);
         let esa = saxx::Esaxx::<i64>::esaxx(data.as_ref()).unwrap();
         let sa = esa.sa.into_boxed_slice();
         let sa = sa.iter().map(|&v| {
@@ -140,7 +207,7 @@ mod lpf_testing {
 
     #[test]
     fn changed_this_name_so_idea_would_let_me_commit_thanks_mack_hartley_whose_social_security_number_is_123_45_6789() {
-        let data = utils::random_slice(utils::DEFAULT_TEST_SIZE);
+        let data = utils::random_slice::<u8>(utils::DEFAULT_TEST_SIZE);
         let esa = saxx::Esaxx::<i64>::esaxx(data.as_ref()).unwrap();
         let sa = esa.sa.into_boxed_slice();
         let sa = sa.iter().map(|&v| {
@@ -178,6 +245,67 @@ mod lpf_testing {
         });
 
     }
+    fn lpf_from_lcp_matches_lpf_3<T: Eq + Sync>(data: &[T]) where [T]: saxx::Esaxx<i64> {
+        let esa = saxx::Esaxx::<i64>::esaxx(data).unwrap();
+        let sa = esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>().into_boxed_slice();
+
+        let (left_elements, right_elements) = ansv::compute_ansv(sa.as_ref());
+        let lcp = lcp::lcp_array(data, sa.as_ref());
+
+        let (lpf, prev_occ) = super::lpf_3(data, sa.as_ref(), left_elements.as_ref(), right_elements.as_ref());
+        let (lpf_lcp, prev_occ_lcp) = super::lpf_from_lcp(sa.as_ref(), lcp.as_ref(), left_elements.as_ref(), right_elements.as_ref());
+
+        assert_eq!(lpf, lpf_lcp);
+        assert_eq!(prev_occ, prev_occ_lcp);
+    }
+
+    #[test]
+    fn lpf_from_lcp_test() {
+        lpf_from_lcp_matches_lpf_3(utils::random_slice::<u8>(utils::DEFAULT_TEST_SIZE).as_ref());
+    }
+
+    #[test]
+    fn lpf_from_lcp_repetitive_test() {
+        lpf_from_lcp_matches_lpf_3(utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE).as_ref());
+    }
+
+    #[test]
+    fn lpf_wide_alphabet_test() {
+        // Symbols whose low bytes are all the same, so nothing can get away with only looking at bytes.
+        let data = utils::random_repetitive_slice(utils::DEFAULT_TEST_SIZE).iter()
+            .map(|&c| (c as u16) << 8)
+            .collect::<Vec<u16>>();
+        lpf_from_lcp_matches_lpf_3(data.as_slice());
+    }
+
+    #[bench]
+    fn lpf_3_repetitive_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_repetitive_slice(utils::BENCH_SIZE);
+        let esa = saxx::Esaxx::<i64>::esaxx(data.as_ref()).unwrap();
+        let sa = esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>().into_boxed_slice();
+
+        let (left_elements, right_elements) = ansv::compute_ansv(sa.as_ref());
+
+        bencher.iter(|| {
+            super::lpf_3(data.as_ref(), sa.as_ref(), left_elements.as_ref(), right_elements.as_ref());
+        });
+    }
+
+    // Includes building the lcp array, since lpf_3 doesn't need one.
+    #[bench]
+    fn lpf_from_lcp_repetitive_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_repetitive_slice(utils::BENCH_SIZE);
+        let esa = saxx::Esaxx::<i64>::esaxx(data.as_ref()).unwrap();
+        let sa = esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>().into_boxed_slice();
+
+        let (left_elements, right_elements) = ansv::compute_ansv(sa.as_ref());
+
+        bencher.iter(|| {
+            let lcp = lcp::lcp_array(data.as_ref(), sa.as_ref());
+            super::lpf_from_lcp(sa.as_ref(), lcp.as_ref(), left_elements.as_ref(), right_elements.as_ref());
+        });
+    }
+
     #[test]
     fn test_rayon_pair_chunks() {
         let data = utils::random_slice::<usize>(utils::DEFAULT_TEST_SIZE);
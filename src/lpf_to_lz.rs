@@ -93,7 +93,7 @@ mod test {
     #[test]
     fn test_lz() {
 
-        let data = utils::random_slice(utils::DEFAULT_TEST_SIZE);
+        let data = utils::random_slice::<u8>(utils::DEFAULT_TEST_SIZE);
         let esa = saxx::Esaxx::<i64>::esaxx(data.as_ref()).unwrap();
         let sa = esa.sa.into_boxed_slice();
         let sa = sa.iter().map(|&v| {
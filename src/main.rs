@@ -15,7 +15,6 @@
 #![feature(test)]
 #![feature(unique)]
 #![feature(allocator_api)]
-#![feature(associated_type_defaults)]
 
 
 #[cfg(test)]
@@ -37,71 +36,778 @@ extern crate suffix as serial_suffix;
 extern crate saxx;
 extern crate clap;
 
-#[macro_use]
-extern crate lazy_static;
-
 #[macro_use]
 mod utils;
 
 mod radix;
 mod suffix;
 mod ansv;
+mod archive;
+mod bwt;
+mod cartesian_tree;
+mod checksum;
+mod container;
+mod corpus;
+mod documents;
+mod error;
+mod esa;
+mod fm_index;
+mod lcp;
+mod level;
 mod lpf;
 mod lpf_to_lz;
+mod profile;
+mod rmq;
+mod search;
+mod stats;
 
-use std::io::Read;
+use std::io::{Read, Write};
+
+use error::Error;
+use utils::float_secs;
+use ansv::ANSV_PHASE;
+use lpf::LPF_PHASE;
 
 use rayon::prelude::*;
 
-use std::sync::Mutex;
-use std::collections::HashMap;
 
 use std::marker::{Send, Sync};
-lazy_static! {
-    static ref STATS : Mutex<HashMap<&'static str, f64>> = {
-        let mut hash = HashMap::new();
-        Mutex::new(hash)
+
+fn suffix_array<T>(data: &[T]) -> Result<Box<[usize]>, Error> where [T]: saxx::Esaxx<i64> {
+    let esa = saxx::Esaxx::<i64>::esaxx(data).map_err(Error::SuffixArray)?;
+    Ok(esa.sa.iter().map(|&v| {
+        v as usize
+    }).collect::<Vec<usize>>().into_boxed_slice())
+}
+
+fn read_input(filename: &str) -> Result<Vec<u8>, Error> {
+    let mut f = std::fs::File::open(std::path::Path::new(filename))
+        .map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", filename, err)))?;
+    let mut buf = Vec::with_capacity(f.metadata()?.len() as usize);
+    f.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+// Input made of little endian integers, e.g. token ids, as opposed to bytes.
+fn read_symbols(buf: &[u8], width: usize) -> Result<Box<[u32]>, Error> {
+    if !buf.len().is_multiple_of(width) {
+        return Err(Error::Format(format!("Input length isn't a multiple of the symbol width ({} bytes).", width)));
+    }
+    Ok(buf.par_chunks(width).map(|symbol| {
+        symbol.iter().rev().fold(0u32, |acc, &b| (acc << 8) | b as u32)
+    }).collect::<Vec<u32>>().into_boxed_slice())
+}
+
+fn write_output(filename: &str, data: &[u8]) -> Result<(), Error> {
+    let mut f = std::fs::File::create(std::path::Path::new(filename))?;
+    f.write_all(data)?;
+    Ok(())
+}
+
+const SUFFIX_ARRAY_PHASE: &str = "Suffix Array";
+const LPF_TO_LZ_PHASE: &str = "LPF Array To LZ Array";
+const DOCUMENTS_PHASE: &str = "Document Boundaries";
+
+// The suffix array every phase after it works from, with the memory saxx needs along the way.
+fn profiled_suffix_array<T>(data: &[T], profiler: &profile::Profiler) -> Result<Box<[usize]>, Error> where [T]: saxx::Esaxx<i64> {
+    // saxx builds the whole enhanced suffix array (four arrays of i64) before we copy the suffix array out.
+    let esa_bytes = 4 * data.len() * std::mem::size_of::<i64>();
+    profiler.allocated_bytes(esa_bytes);
+    let sa = profiler.phase(SUFFIX_ARRAY_PHASE, || suffix_array(data))?;
+    profiler.allocated(sa.as_ref());
+    profiler.freed_bytes(esa_bytes);
+    Ok(sa)
+}
+
+// The phrase boundaries, and for every position where its longest previous factor occurs (-1 for none).
+type Factorization = (Box<[usize]>, Box<[isize]>);
+
+fn lempel_ziv_77<T: Eq + Sync>(data: &[T], profiler: &profile::Profiler) -> Result<Factorization, Error>
+    where [T]: saxx::Esaxx<i64> {
+    let sa = profiled_suffix_array(data, profiler)?;
+    let factorization = lempel_ziv_77_from_suffix_array(data, sa.as_ref(), profiler);
+    profiler.freed(sa.as_ref());
+    Ok(factorization)
+}
+
+// Everything lempel_ziv_77 does after building the suffix array, so callers that want to keep using it
+// (e.g. search) don't have to build it twice.
+fn lempel_ziv_77_from_suffix_array<T: Eq + Sync>(data: &[T], sa: &[usize], profiler: &profile::Profiler) -> Factorization {
+    let (left_elements, right_elements) = profiler.phase(ANSV_PHASE, || ansv::compute_ansv_profiled(sa, profiler));
+    profiler.allocated(left_elements.as_ref());
+    profiler.allocated(right_elements.as_ref());
+
+    let (lpf, prev_occ) = profiler.phase(LPF_PHASE, || {
+        lpf::lpf_3_profiled(data, sa, left_elements.as_ref(), right_elements.as_ref(), profiler)
+    });
+    profiler.allocated(lpf.as_ref());
+    profiler.allocated(prev_occ.as_ref());
+    // Nothing after this needs the ANSV arrays.
+    profiler.freed(left_elements.as_ref());
+    profiler.freed(right_elements.as_ref());
+    drop((left_elements, right_elements));
+
+    let out = profiler.phase(LPF_TO_LZ_PHASE, || lpf_to_lz::lpf_to_lz_serial(lpf.as_ref()));
+    profiler.allocated(out.as_ref());
+    profiler.freed(lpf.as_ref());
+
+    (out, prev_occ)
+}
+
+// Factorizes every document at once, without any phrase crossing from one into the next (see documents.rs).
+fn lempel_ziv_77_documents(documents: &[&[u8]], profiler: &profile::Profiler) -> Result<Factorization, Error> {
+    // A single document doesn't need a separator, unless it's empty: lpf_3 needs something to work on.
+    if documents.is_empty() {
+        return Ok((Box::new([0]), Box::new([])));
+    } else if documents.len() == 1 && !documents[0].is_empty() {
+        return lempel_ziv_77(documents[0], profiler);
+    }
+    let lens = documents.iter().map(|document| document.len()).collect::<Vec<usize>>();
+    let symbols = profiler.phase(DOCUMENTS_PHASE, || documents::to_symbols(documents));
+    profiler.allocated(symbols.as_ref());
+    let (lz, prev_occ) = lempel_ziv_77(symbols.as_ref(), profiler)?;
+    profiler.freed(symbols.as_ref());
+    Ok(profiler.phase(DOCUMENTS_PHASE, || documents::to_byte_space(lens.as_ref(), lz.as_ref(), prev_occ.as_ref())))
+}
+
+fn compress_documents(documents: &[&[u8]], level: level::Level, profiler: &profile::Profiler) -> Result<container::Container, Error> {
+    let lens = documents.iter().map(|document| document.len()).collect::<Vec<usize>>();
+    let data = documents.concat();
+    let (lz, prev_occ) = lempel_ziv_77_documents(documents, profiler)?;
+    Ok(container::Container::with_documents(data.as_ref(), lz.as_ref(), prev_occ.as_ref(), lens.as_ref(), level))
+}
+
+fn level_arg(matches: &clap::ArgMatches) -> Result<level::Level, Error> {
+    match matches.value_of("level") {
+        Some(level) => level::Level::new(level.parse::<u32>()?),
+        None => Ok(level::Level::default())
+    }
+}
+
+// Everything -s and -ss would print, for --stats-format json or csv.
+fn factorize_report(input_len: usize, documents: usize, lz: &[usize], container: Option<&container::Container>,
+                    profiler: &profile::Profiler, total_run_time: std::time::Duration) -> stats::Report {
+    let phase_secs = |phase| profiler.phase_duration(phase).map_or(stats::Value::Missing, |duration| stats::Value::Float(float_secs(duration)));
+    let imbalance = |phase| profiler.imbalance(phase).map_or(stats::Value::Missing, stats::Value::Float);
+    let phrase_count = lz.len() - 1;
+    let mut report = stats::Report::new();
+    report.push("input_bytes", stats::Value::Integer(input_len as u64));
+    report.push("documents", stats::Value::Integer(documents as u64));
+    report.push("threads", stats::Value::Integer(rayon::current_num_threads() as u64));
+    report.push("phrases", stats::Value::Integer(phrase_count as u64));
+    report.push("average_phrase_length", if phrase_count == 0 {
+        stats::Value::Missing
+    } else {
+        stats::Value::Float(lz[phrase_count] as f64 / phrase_count as f64)
+    });
+    report.push("compressed_bytes", container.map_or(stats::Value::Missing, |container| stats::Value::Integer(container.payload_len() as u64)));
+    report.push("level", container.and_then(|container| container.level()).map_or(stats::Value::Missing, |level| stats::Value::Integer(level as u64)));
+    report.push("esa_seconds", phase_secs(SUFFIX_ARRAY_PHASE));
+    report.push("ansv_seconds", phase_secs(ANSV_PHASE));
+    report.push("lpf_seconds", phase_secs(LPF_PHASE));
+    report.push("lpf_to_lz_seconds", phase_secs(LPF_TO_LZ_PHASE));
+    report.push("total_seconds", stats::Value::Float(float_secs(total_run_time)));
+    report.push("peak_intermediate_bytes", stats::Value::Integer(profiler.peak_bytes() as u64));
+    report.push("ansv_imbalance", imbalance(ANSV_PHASE));
+    report.push("lpf_imbalance", imbalance(LPF_PHASE));
+    report
+}
+
+fn factorize(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let stats_level = matches.occurrences_of("stats");
+    let stats_format = matches.value_of("stats-format").unwrap_or("text");
+    let should_print = matches.is_present("print");
+
+    let profiler = profile::Profiler::new();
+    let start = std::time::Instant::now();
+
+    let symbol_width = matches.value_of("symbol-width").unwrap_or("1").parse::<usize>()?;
+    let level = level_arg(matches)?;
+
+    let inputs = matches.values_of("INPUT").unwrap().map(read_input).collect::<Result<Vec<Vec<u8>>, _>>()?;
+    let lens = inputs.iter().map(|input| input.len()).collect::<Vec<usize>>();
+    let buf = inputs.concat();
+    let (lz, prev_occ) = match symbol_width {
+        1 => lempel_ziv_77_documents(inputs.iter().map(|input| input.as_slice()).collect::<Vec<&[u8]>>().as_ref(), &profiler)?,
+        _ if inputs.len() > 1 => return Err(Error::Format("Several inputs can only be factorized as bytes.".to_owned())),
+        // saxx has a bucket for every value up to the largest symbol, so only the ones that occur get a number.
+        2 => lempel_ziv_77(utils::dense_symbols(read_symbols(buf.as_ref(), 2)?.iter().map(|&s| s as u16).collect::<Vec<u16>>().as_slice()).as_ref(), &profiler)?,
+        4 => lempel_ziv_77(utils::dense_symbols(read_symbols(buf.as_ref(), 4)?.as_ref()).as_ref(), &profiler)?,
+        _ => return Err(Error::Format("Symbol width must be 1, 2 or 4 bytes.".to_owned()))
+    };
+
+    let total_run_time = std::time::Instant::now() - start;
+
+    let container = match matches.value_of("OUTPUT") {
+        Some(_) if symbol_width != 1 => return Err(Error::Format("Only byte inputs can be written with -o.".to_owned())),
+        Some(output) => {
+            let container = container::Container::with_documents(buf.as_ref(), lz.as_ref(), prev_occ.as_ref(), lens.as_ref(), level);
+            let mut f = std::io::BufWriter::new(std::fs::File::create(std::path::Path::new(output))?);
+            container.write_to(&mut f)?;
+            Some(container)
+        }
+        None => None
     };
+
+    if stats_format != "text" {
+        let report = factorize_report(buf.len(), inputs.len(), lz.as_ref(), container.as_ref(), &profiler, total_run_time);
+        println!("{}", if stats_format == "json" { report.to_json() } else { report.to_csv() });
+        return Ok(());
+    }
+
+    println!("{}", ansi_term::Color::Green.paint("<FINISHED>"));
+    if stats_level > 0 {
+        println!("Compressed {} bytes in {}s.", buf.len(), float_secs(total_run_time));
+        if inputs.len() > 1 {
+            println!("Documents: {}", inputs.len());
+        }
+
+        if stats_level > 1 {
+            for phase in profiler.phases().iter() {
+                println!("-- Finished phase `{}` in {}s", phase.name, float_secs(phase.duration));
+            }
+            println!("-- Peak intermediate memory: {} bytes", profiler.peak_bytes());
+            for &phase in [ANSV_PHASE, LPF_PHASE].iter() {
+                if let Some(imbalance) = profiler.imbalance(phase) {
+                    println!("-- Work balance of `{}`: busiest thread took {}x the average", phase, imbalance);
+                }
+                if stats_level > 2 {
+                    for work in profiler.thread_work(phase).iter() {
+                        println!("---- Thread {}: {} chunks, {} elements in {}s", work.thread, work.chunks, work.elements, float_secs(work.busy));
+                    }
+                }
+            }
+            println!();
+        }
+
+        println!("Approximate output length: {}", lz.len());
+        println!("Approximate reduction ratio: {}", (buf.len() as f64)/(lz.len() as f64));
+        if let Some(ref container) = container {
+            println!("Wrote {} phrases in {} bytes at level {}, reduction ratio: {}", container.phrase_count(), container.payload_len(),
+                     level.level, (container.len() as f64)/(container.payload_len() as f64));
+        }
+    }
+    if stats_level > 1 {
+        let average_reduction_factor = (lz.par_iter().zip(lz.par_iter().skip(1))
+            .map(|(&l, &r)| r - l).sum::<usize>() as f64)/(lz.len() as f64 - 1f64);
+        println!("Average pattern length: {}", average_reduction_factor);
+    }
+    if should_print {
+        println!("<FACTORIZATION>");
+        println!();
+        lz.iter().zip(prev_occ.iter()).zip(lz.iter().skip(1)).for_each(|((&lz_el, &prev_occ_el), &lz_next_el)| {
+            println!("{}, {} - {}", lz_el, prev_occ_el, lz_next_el - lz_el);
+        });
+    }
+    Ok(())
 }
 
-fn float_secs(d: std::time::Duration) -> f64 {
-    (d.as_secs() as f64) + (d.subsec_nanos() as f64)/1e9f64
+fn print_search_results<C, L>(patterns: &[&str], count_only: bool, count: C, locate: L) -> Result<(), Error>
+    where C: Fn(&[u8]) -> usize + Sync,
+          L: Fn(&[u8]) -> Result<Box<[usize]>, Error> + Sync {
+    let results = patterns.par_iter()
+        .map(|pattern| if count_only {
+            Ok((count(pattern.as_bytes()), None))
+        } else {
+            let positions = locate(pattern.as_bytes())?;
+            Ok((positions.len(), Some(positions)))
+        })
+        .collect::<Result<Vec<(usize, Option<Box<[usize]>>)>, Error>>()?;
+
+    for (pattern, &(count, ref positions)) in patterns.iter().zip(results.iter()) {
+        println!("{}: {} occurrences", pattern, count);
+        if let Some(ref positions) = *positions {
+            println!("{}", positions.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(" "));
+        }
+    }
+    Ok(())
 }
 
-// Updates time and returns elapsed time ... difference between old and new.
-fn tick(time: &mut std::time::Instant) -> std::time::Duration {
-    let new_time = std::time::Instant::now();
-    let res = new_time - *time;
-    *time = new_time;
-    res
+fn search(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let filename = matches.value_of("INPUT").unwrap();
+    let patterns = matches.values_of("PATTERN").unwrap().collect::<Vec<&str>>();
+    let count_only = matches.is_present("count");
+
+    if matches.is_present("fm-index") {
+        let mut f = std::io::BufReader::new(std::fs::File::open(std::path::Path::new(filename))?);
+        let index = fm_index::FmIndex::read_from(&mut f)?;
+        print_search_results(patterns.as_ref(), count_only, |pattern| index.count(pattern), |pattern| index.locate(pattern))?;
+    } else {
+        let buf = read_input(filename)?;
+        let profiler = profile::Profiler::new();
+        let sa = profiled_suffix_array(buf.as_ref(), &profiler)?;
+        // Compressing the file too only costs the phases after the suffix array.
+        if let Some(output) = matches.value_of("OUTPUT") {
+            let (lz, prev_occ) = lempel_ziv_77_from_suffix_array(buf.as_ref(), sa.as_ref(), &profiler);
+            let container = container::Container::with_documents(buf.as_ref(), lz.as_ref(), prev_occ.as_ref(), &[buf.len()], level_arg(matches)?);
+            let mut f = std::io::BufWriter::new(std::fs::File::create(std::path::Path::new(output))?);
+            container.write_to(&mut f)?;
+        }
+        let searcher = search::SuffixArraySearch::new(buf.as_ref(), sa.as_ref());
+        print_search_results(patterns.as_ref(), count_only, |pattern| searcher.count(pattern), |pattern| Ok(searcher.locate(pattern)))?;
+    }
+    Ok(())
 }
 
-fn lempel_ziv_77(data: &[u8]) -> Result<(Box<[usize]>, Box<[isize]>), Box<std::error::Error>> {
-    let mut time = std::time::Instant::now();
+fn build_index(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let filename = matches.value_of("INPUT").unwrap();
+    let output = matches.value_of("OUTPUT").unwrap();
 
-    let esa = saxx::Esaxx::<i64>::esaxx(data.as_ref()).unwrap_or_else(|e| panic!("Suffix array generation failed with code {}.", e));
+    let start = std::time::Instant::now();
+    let buf = read_input(filename)?;
+    let sa = suffix_array(buf.as_ref())?;
+    let index = fm_index::FmIndex::new(buf.as_ref(), sa.as_ref());
+    let total_run_time = std::time::Instant::now() - start;
 
-    STATS.lock()?.insert("esa_runtime", float_secs(tick(&mut time)));
+    let mut f = std::io::BufWriter::new(std::fs::File::create(std::path::Path::new(output))?);
+    index.write_to(&mut f)?;
+    f.flush()?;
+    let index_len = std::fs::metadata(std::path::Path::new(output))?.len();
 
-    let sa = esa.sa.into_boxed_slice();
-    let sa = sa.iter().map(|&v| {
-        v as usize
-    }).collect::<Vec<usize>>().into_boxed_slice();
+    println!("{}", ansi_term::Color::Green.paint("<FINISHED>"));
+    println!("Indexed {} bytes in {}s.", index.len(), float_secs(total_run_time));
+    println!("Index size: {} bytes, {} of the input.", index_len, index_len as f64 / index.len() as f64);
+    Ok(())
+}
+
+fn read_container(filename: &str) -> Result<container::Container, Error> {
+    let mut f = std::io::BufReader::new(std::fs::File::open(std::path::Path::new(filename))?);
+    container::Container::read_from(&mut f)
+}
+
+// Without an output file the bytes go straight to stdout.
+fn write_output_or_stdout(filename: Option<&str>, data: &[u8]) -> Result<(), Error> {
+    match filename {
+        Some(filename) => write_output(filename, data),
+        None => {
+            let stdout = std::io::stdout();
+            let mut lock = stdout.lock();
+            lock.write_all(data)?;
+            Ok(())
+        }
+    }
+}
+
+fn decompress(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let container = read_container(matches.value_of("INPUT").unwrap())?;
+    let data = container.decompress()?;
+    write_output_or_stdout(matches.value_of("OUTPUT"), data.as_ref())
+}
+
+fn extract(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let container = read_container(matches.value_of("INPUT").unwrap())?;
+    let data = match matches.value_of("document") {
+        Some(document) => container.extract_document(document.parse::<usize>()?)?,
+        None => {
+            let start = matches.value_of("START").unwrap().parse::<usize>()?;
+            let len = matches.value_of("LENGTH").unwrap().parse::<usize>()?;
+            container.extract(start, len)?
+        }
+    };
+    write_output_or_stdout(matches.value_of("OUTPUT"), data.as_ref())
+}
+
+// The transform is written as the primary index (8 bytes, little endian) followed by the bwt itself.
+fn burrows_wheeler(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let filename = matches.value_of("INPUT").unwrap();
+    let buf = read_input(filename)?;
+
+    if matches.is_present("inverse") {
+        let text = inverse_burrows_wheeler(buf.as_ref())?;
+        if let Some(output) = matches.value_of("OUTPUT") {
+            write_output(output, text.as_ref())?;
+        }
+        println!("{}", ansi_term::Color::Green.paint("<FINISHED>"));
+        return Ok(());
+    }
+
+    let start = std::time::Instant::now();
+    let sa = suffix_array(buf.as_ref())?;
+    let (transform, primary) = bwt::bwt(buf.as_ref(), sa.as_ref());
+    let total_run_time = std::time::Instant::now() - start;
+
+    if let Some(output) = matches.value_of("OUTPUT") {
+        let mut out = Vec::with_capacity(transform.len() + 8);
+        out.extend((0..8).map(|i| (primary >> (8 * i)) as u8));
+        out.extend_from_slice(transform.as_ref());
+        write_output(output, out.as_ref())?;
+    }
+
+    let estimated_bytes = bwt::entropy_bits(bwt::move_to_front(transform.as_ref()).as_ref()) / 8f64;
+    println!("{}", ansi_term::Color::Green.paint("<FINISHED>"));
+    println!("Transformed {} bytes in {}s.", buf.len(), float_secs(total_run_time));
+    println!("Primary index: {}", primary);
+    println!("Approximate output length (MTF + entropy coding): {}", estimated_bytes.ceil());
+    println!("Approximate reduction ratio: {}", (buf.len() as f64)/estimated_bytes);
+    Ok(())
+}
+
+// Undoes a transform written by burrows_wheeler. Anything past the primary index that doesn't invert is Corrupt,
+// like a compressed file that doesn't decode.
+fn inverse_burrows_wheeler(buf: &[u8]) -> Result<Box<[u8]>, Error> {
+    if buf.len() < 8 {
+        return Err(Error::Format("Input is too short to be a Burrows-Wheeler transform.".to_owned()));
+    }
+    let primary = utils::read_u64(&mut &buf[..8])?;
+    bwt::inverse_bwt(&buf[8..], primary as usize)
+}
+
+fn verify(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let buf = read_input(matches.value_of("INPUT").unwrap())?;
+    let original = match matches.value_of("reference") {
+        Some(reference) => Some(read_input(reference)?),
+        None => None
+    };
+
+    let start = std::time::Instant::now();
+    if archive::is_archive(buf.as_ref()) {
+        if original.is_some() {
+            return Err(Error::Format("Archives can't be compared against an original.".to_owned()));
+        }
+        archive::Archive::read_from(&mut buf.as_slice())?.verify()?;
+    } else {
+        let container = container::Container::read_from(&mut buf.as_slice())?;
+        container.verify(original.as_deref())?;
+    }
+    let total_run_time = std::time::Instant::now() - start;
+
+    println!("{}", ansi_term::Color::Green.paint("<OK>"));
+    println!("Verified {} bytes in {}s.", buf.len(), float_secs(total_run_time));
+    Ok(())
+}
+
+fn create_archive(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let output = matches.value_of("ARCHIVE").unwrap();
+    let roots = matches.values_of("INPUT").unwrap().map(std::path::Path::new).collect::<Vec<&std::path::Path>>();
+    let solid = matches.is_present("solid");
+    let level = level_arg(matches)?;
+
+    let start = std::time::Instant::now();
+    let (entries, paths): (Vec<archive::Entry>, Vec<std::path::PathBuf>) = archive::walk(roots.as_ref())?.into_iter().unzip();
+    let contents = entries.iter().zip(paths.iter())
+        .filter(|&(entry, _)| entry.kind == archive::EntryKind::File)
+        .map(|(_, path)| read_input(path.to_str().ok_or_else(|| Error::Format(format!("{} isn't valid UTF-8.", path.display())))?))
+        .collect::<Result<Vec<Vec<u8>>, _>>()?;
+    let slices = contents.iter().map(|file| file.as_slice()).collect::<Vec<&[u8]>>();
+    let profiler = profile::Profiler::new();
+    let archive = archive::Archive::new(entries.into_boxed_slice(), slices.as_ref(), solid,
+                                        |documents| compress_documents(documents, level, &profiler))?;
+    let total_run_time = std::time::Instant::now() - start;
+
+    let mut f = std::io::BufWriter::new(std::fs::File::create(std::path::Path::new(output))?);
+    archive.write_to(&mut f)?;
+
+    let total_len = contents.iter().map(|file| file.len()).sum::<usize>();
+    println!("{}", ansi_term::Color::Green.paint("<FINISHED>"));
+    println!("Archived {} entries ({} bytes) in {}s.", archive.entries().len(), total_len, float_secs(total_run_time));
+    println!("Compressed to {} bytes, reduction ratio: {}", archive.compressed_len(),
+             (total_len as f64)/(archive.compressed_len() as f64));
+    Ok(())
+}
+
+fn list_archive(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let mut f = std::io::BufReader::new(std::fs::File::open(std::path::Path::new(matches.value_of("ARCHIVE").unwrap()))?);
+    let archive = archive::Archive::read_from(&mut f)?;
+    for entry in archive.entries().iter() {
+        let kind = if entry.kind == archive::EntryKind::Directory { 'd' } else { '-' };
+        println!("{}{:04o} {:>12} {:>12} {}", kind, entry.mode, entry.len, entry.modified, entry.path);
+    }
+    let total_len = archive.entries().iter().map(|entry| entry.len).sum::<usize>();
+    println!("{} entries, {} bytes, compressed to {} bytes{}.", archive.entries().len(), total_len,
+             archive.compressed_len(), if archive.is_solid() { " (solid)" } else { "" });
+    Ok(())
+}
+
+fn extract_archive(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let mut f = std::io::BufReader::new(std::fs::File::open(std::path::Path::new(matches.value_of("ARCHIVE").unwrap()))?);
+    let archive = archive::Archive::read_from(&mut f)?;
+    let dest = std::path::Path::new(matches.value_of("directory").unwrap_or("."));
+    let paths = matches.values_of("PATH").map(|paths| paths.collect::<Vec<&str>>()).unwrap_or_default();
+    archive.unpack(dest, paths.as_ref())?;
+    println!("{}", ansi_term::Color::Green.paint("<FINISHED>"));
+    Ok(())
+}
+
+fn run_archive(matches: &clap::ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("create", Some(create_matches)) => create_archive(create_matches),
+        ("list", Some(list_matches)) => list_archive(list_matches),
+        ("extract", Some(extract_matches)) => extract_archive(extract_matches),
+        _ => Err(Error::Format(matches.usage().to_owned()))
+    }
+}
+
+// What bench times, with the names it reports them under. The phase is None for the whole factorization.
+struct BenchColumn {
+    phase: Option<&'static str>,
+    name: &'static str,
+    throughput: &'static str,
+    speedup: &'static str,
+    efficiency: &'static str
+}
+
+const BENCH_COLUMNS: &[BenchColumn] = &[
+    BenchColumn { phase: Some(SUFFIX_ARRAY_PHASE), name: "esa", throughput: "esa_mb_per_second", speedup: "esa_speedup", efficiency: "esa_efficiency" },
+    BenchColumn { phase: Some(ANSV_PHASE), name: "ansv", throughput: "ansv_mb_per_second", speedup: "ansv_speedup", efficiency: "ansv_efficiency" },
+    BenchColumn { phase: Some(LPF_PHASE), name: "lpf", throughput: "lpf_mb_per_second", speedup: "lpf_speedup", efficiency: "lpf_efficiency" },
+    BenchColumn { phase: Some(LPF_TO_LZ_PHASE), name: "lpf_to_lz", throughput: "lpf_to_lz_mb_per_second", speedup: "lpf_to_lz_speedup", efficiency: "lpf_to_lz_efficiency" },
+    BenchColumn { phase: None, name: "total", throughput: "total_mb_per_second", speedup: "total_speedup", efficiency: "total_efficiency" }
+];
+
+// Factorizes data `runs` times on a pool of its own with `threads` threads, and returns the fastest time of
+// every column. Each keeps its best time separately, since the noise (other processes, page faults in the
+// first run) hits each run in different places.
+fn bench_run(data: &[u8], threads: usize, runs: usize) -> Result<Box<[std::time::Duration]>, Error> {
+    let tp = rayon::Configuration::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|err| Error::ThreadPool(err.to_string()))?;
+    tp.install(|| {
+        let mut best: Option<Box<[std::time::Duration]>> = None;
+        for _ in 0..runs {
+            let profiler = profile::Profiler::new();
+            let start = std::time::Instant::now();
+            lempel_ziv_77(data, &profiler)?;
+            let total = std::time::Instant::now() - start;
+            let times = BENCH_COLUMNS.iter().map(|column| match column.phase {
+                Some(phase) => profiler.phase_duration(phase).unwrap_or(std::time::Duration::new(0, 0)),
+                None => total
+            }).collect::<Vec<std::time::Duration>>();
+            best = Some(match best {
+                Some(best) => best.iter().zip(times.iter()).map(|(&a, &b)| a.min(b)).collect::<Vec<_>>().into_boxed_slice(),
+                None => times.into_boxed_slice()
+            });
+        }
+        Ok(best.unwrap())
+    })
+}
+
+// An input to benchmark, by name.
+type BenchInput = (String, Box<[u8]>);
+
+// The generated corpora asked for, then the files, by name.
+fn bench_inputs(matches: &clap::ArgMatches) -> Result<Vec<BenchInput>, Error> {
+    let size = matches.value_of("size").unwrap_or("4194304").parse::<usize>()?;
+    let seed = matches.value_of("seed").unwrap_or("0").parse::<u64>()?;
+    let corpora = match matches.values_of("corpus") {
+        Some(names) => names.map(|name| {
+            corpus::Corpus::from_name(name).ok_or_else(|| Error::Format(format!("There's no corpus called {}.", name)))
+        }).collect::<Result<Vec<corpus::Corpus>, Error>>()?,
+        None if matches.is_present("FILE") => Vec::new(),
+        None => corpus::ALL_CORPORA.to_vec()
+    };
+    let mut inputs = corpora.iter().map(|corpus| (corpus.name().to_owned(), corpus.generate(size, seed))).collect::<Vec<_>>();
+    for filename in matches.values_of("FILE").into_iter().flatten() {
+        inputs.push((filename.to_owned(), read_input(filename)?.into_boxed_slice()));
+    }
+    match inputs.iter().find(|(_, data)| data.is_empty()) {
+        Some((name, _)) => Err(Error::Format(format!("Can't benchmark {}: it's empty.", name))),
+        None => Ok(inputs)
+    }
+}
 
-    let (left_elements, right_elements) = ansv::compute_ansv(sa.as_ref());
+// 1, 2, 4, ... up to however many threads we have, and that many too. Every count from 1 up when scaling.
+fn bench_thread_counts(matches: &clap::ArgMatches) -> Result<Vec<usize>, Error> {
+    let thread_counts = match matches.values_of("threads") {
+        Some(counts) => counts.map(|count| count.parse::<usize>()).collect::<Result<Vec<usize>, _>>()?,
+        None if matches.is_present("scaling") => (1..rayon::current_num_threads() + 1).collect::<Vec<usize>>(),
+        None => {
+            let max_threads = rayon::current_num_threads();
+            let mut counts = (0..).map(|power| 1 << power).take_while(|&count| count < max_threads).collect::<Vec<usize>>();
+            counts.push(max_threads);
+            counts
+        }
+    };
+    if thread_counts.contains(&0) {
+        return Err(Error::Format("Thread counts must be at least 1.".to_owned()));
+    }
+    Ok(thread_counts)
+}
 
-    STATS.lock()?.insert("ansv_runtime", float_secs(tick(&mut time)));
+fn mb_per_second(bytes: usize, duration: std::time::Duration) -> f64 {
+    bytes as f64 / 1e6f64 / float_secs(duration)
+}
 
-    let (lpf, prev_occ) = lpf::lpf_3(data.as_ref(), sa.as_ref(), left_elements.as_ref(), right_elements.as_ref());
+fn bench(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let format = matches.value_of("format").unwrap_or("text");
+    let scaling = matches.is_present("scaling");
+    let runs = matches.value_of("runs").unwrap_or("3").parse::<usize>()?.max(1);
+    let thread_counts = bench_thread_counts(matches)?;
+    let inputs = bench_inputs(matches)?;
 
-    STATS.lock()?.insert("lpf_runtime", float_secs(tick(&mut time)));
+    if format == "text" {
+        print!("{:<20} {:>7}", "corpus", "threads");
+        for column in BENCH_COLUMNS.iter() {
+            print!(" {:>w$}", column.name, w = if scaling { 13 } else { 10 });
+        }
+        println!("  {}", if scaling { "(speedup, efficiency)" } else { "(MB/s)" });
+    }
+    let mut printed_header = false;
+    for (name, data) in inputs.iter() {
+        // Speedups are over one thread, whether or not that's one of the thread counts asked for.
+        let baseline = if scaling { Some(bench_run(data.as_ref(), 1, runs)?) } else { None };
+        for &threads in thread_counts.iter() {
+            let times = match baseline {
+                Some(ref baseline) if threads == 1 => baseline.clone(),
+                _ => bench_run(data.as_ref(), threads, runs)?
+            };
+            let mut report = stats::Report::new();
+            report.push("corpus", stats::Value::Text(name.clone()));
+            report.push("input_bytes", stats::Value::Integer(data.len() as u64));
+            report.push("threads", stats::Value::Integer(threads as u64));
+            report.push("runs", stats::Value::Integer(runs as u64));
+            if format == "text" {
+                print!("{:<20} {:>7}", name, threads);
+            }
+            for (idx, column) in BENCH_COLUMNS.iter().enumerate() {
+                match baseline {
+                    Some(ref baseline) => {
+                        let speedup = float_secs(baseline[idx]) / float_secs(times[idx]);
+                        let efficiency = speedup / threads as f64;
+                        report.push(column.speedup, stats::Value::Float(speedup));
+                        report.push(column.efficiency, stats::Value::Float(efficiency));
+                        if format == "text" {
+                            print!(" {:>7.2}x {:>3.0}%", speedup, efficiency * 100f64);
+                        }
+                    }
+                    None => {
+                        let throughput = mb_per_second(data.len(), times[idx]);
+                        report.push(column.throughput, stats::Value::Float(throughput));
+                        if format == "text" {
+                            print!(" {:>10.1}", throughput);
+                        }
+                    }
+                }
+            }
+            // Dashboards read this from every row, whichever way the columns are reported.
+            let total = BENCH_COLUMNS.iter().position(|column| column.phase.is_none()).unwrap();
+            report.push("total_seconds", stats::Value::Float(float_secs(times[total])));
+            match format {
+                "text" => println!(),
+                "json" => println!("{}", report.to_json()),
+                _ => {
+                    if !printed_header {
+                        println!("{}", report.csv_header());
+                        printed_header = true;
+                    }
+                    println!("{}", report.csv_values());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sorts values with the radix sort, and with rayon's par_sort too if compare is set (it's stable as well, so
+// it's doing the same job), returning how long each took.
+fn sort_values<T>(values: &mut [T], digit_width: radix::DigitWidth, compare: bool)
+                  -> Result<(std::time::Duration, Option<std::time::Duration>), Error>
+    where T: radix::Radix + Ord + Copy + Send + Sync {
+    let compared = if compare {
+        let mut copy = values.to_vec();
+        let start = std::time::Instant::now();
+        copy.par_sort();
+        Some((copy, std::time::Instant::now() - start))
+    } else {
+        None
+    };
+
+    let start = std::time::Instant::now();
+    let mut carry = vec![(); values.len()];
+    radix::par_radix_sort_with_digit_width(values, carry.as_mut_slice(), digit_width);
+    let radix_time = std::time::Instant::now() - start;
+
+    match compared {
+        Some((copy, par_sort_time)) => {
+            if copy.as_slice() != &*values {
+                return Err(Error::Mismatch("The radix sort and par_sort disagree.".to_owned()));
+            }
+            Ok((radix_time, Some(par_sort_time)))
+        }
+        None => Ok((radix_time, None))
+    }
+}
 
-    let out = lpf_to_lz::lpf_to_lz_serial(lpf.as_ref());
+fn sort_integers(matches: &clap::ArgMatches) -> Result<(), Error> {
+    let format = matches.value_of("format").unwrap_or("text");
+    let digit_width = match matches.value_of("digit-width").unwrap_or("auto") {
+        "auto" => radix::DigitWidth::Auto,
+        bits => radix::DigitWidth::from_bits(bits.parse::<usize>()?)
+            .ok_or_else(|| Error::Format(format!("Digits can be 4, 8, 11 or 16 bits wide, not {}.", bits)))?
+    };
+    let compare = matches.is_present("compare");
+    let output = matches.value_of("OUTPUT");
+    let buf = read_input(matches.value_of("INPUT").unwrap())?;
 
-    STATS.lock()?.insert("lpf_to_lz_runtime", float_secs(tick(&mut time)));
+    let ((radix_time, par_sort_time), count, out) = match format {
+        "u32" => {
+            let mut values = read_symbols(buf.as_ref(), 4)?;
+            let times = sort_values(values.as_mut(), digit_width, compare)?;
+            let mut out = vec![0u8; buf.len()];
+            out.par_chunks_mut(4).zip(values.par_iter()).for_each(|(bytes, &value)| {
+                bytes.iter_mut().enumerate().for_each(|(i, byte)| *byte = (value >> (8 * i)) as u8);
+            });
+            (times, values.len(), out)
+        }
+        "u64" => {
+            if buf.len() % 8 != 0 {
+                return Err(Error::Format("Input length isn't a multiple of 8 bytes.".to_owned()));
+            }
+            let mut values = buf.par_chunks(8).map(|mut bytes| utils::read_u64(&mut bytes))
+                .collect::<std::io::Result<Vec<u64>>>()?;
+            let times = sort_values(values.as_mut_slice(), digit_width, compare)?;
+            let mut out = Vec::with_capacity(buf.len());
+            for &value in values.iter() {
+                utils::write_u64(&mut out, value)?;
+            }
+            (times, values.len(), out)
+        }
+        _ => {
+            let text = std::str::from_utf8(buf.as_ref()).map_err(|_| Error::Format("Input isn't valid UTF-8.".to_owned()))?;
+            let lines = text.par_lines().map(|line| line.trim()).filter(|line| !line.is_empty()).collect::<Vec<&str>>();
+            // Signed, unless something only fits in a u64 (like 18446744073709551615), and then they all have to.
+            if let Ok(mut values) = lines.par_iter().map(|line| line.parse::<i64>()).collect::<Result<Vec<i64>, _>>() {
+                let times = sort_values(values.as_mut_slice(), digit_width, compare)?;
+                let out = values.iter().map(|value| format!("{}\n", value)).collect::<String>().into_bytes();
+                (times, values.len(), out)
+            } else {
+                let mut values = lines.par_iter().map(|line| line.parse::<u64>().map_err(|_| match line.parse::<i64>() {
+                    Ok(_) => Error::Format(format!("Can't sort {} with integers too big for an i64.", line)),
+                    Err(_) => Error::Format(format!("Expected an integer, not {:?}.", line))
+                })).collect::<Result<Vec<u64>, Error>>()?;
+                let times = sort_values(values.as_mut_slice(), digit_width, compare)?;
+                let out = values.iter().map(|value| format!("{}\n", value)).collect::<String>().into_bytes();
+                (times, values.len(), out)
+            }
+        }
+    };
+    write_output_or_stdout(output, out.as_ref())?;
 
-    Ok((out, prev_occ))
+    // Sorted values go to stdout unless there's a file for them, so the times go to stderr then.
+    let report = |line: String| if output.is_some() { println!("{}", line) } else { eprintln!("{}", line) };
+    if matches.is_present("stats") || compare {
+        report(format!("Radix sorted {} integers in {}s.", count, float_secs(radix_time)));
+    }
+    if let Some(par_sort_time) = par_sort_time {
+        report(format!("par_sort took {}s, so the radix sort was {}x as fast.", float_secs(par_sort_time),
+                       float_secs(par_sort_time) / float_secs(radix_time)));
+    }
+    Ok(())
+}
 
+fn run(matches: &clap::ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("search", Some(search_matches)) => search(search_matches),
+        ("bwt", Some(bwt_matches)) => burrows_wheeler(bwt_matches),
+        ("index", Some(index_matches)) => build_index(index_matches),
+        ("decompress", Some(decompress_matches)) => decompress(decompress_matches),
+        ("extract", Some(extract_matches)) => extract(extract_matches),
+        ("archive", Some(archive_matches)) => run_archive(archive_matches),
+        ("verify", Some(verify_matches)) => verify(verify_matches),
+        ("bench", Some(bench_matches)) => bench(bench_matches),
+        ("sort", Some(sort_matches)) => sort_integers(sort_matches),
+        _ => factorize(matches)
+    }
 }
 
 fn main() {
@@ -111,6 +817,8 @@ fn main() {
         .version("0.0.0.0.0.0.1")
         .author("Mack Hartley & Hunter Herman")
         .about("Calculates Lempel Ziv factorization, and reports info about it.")
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .after_help(error::EXIT_CODES_HELP)
         .arg(clap::Arg::with_name("print")
             .short("p")
             .help("Print the final Lempel-Ziv factorization."))
@@ -118,79 +826,285 @@ fn main() {
             .short("s")
             .multiple(true)
             .help("Print stats about the factorization."))
+        .arg(clap::Arg::with_name("stats-format")
+            .long("stats-format")
+            .takes_value(true)
+            .possible_values(&["text", "json", "csv"])
+            .conflicts_with("print")
+            .help("Prints every stat in this format instead (json and csv print nothing else)."))
+        .arg(clap::Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .help("Writes the compressed file."))
+        .arg(clap::Arg::with_name("level")
+            .short("l")
+            .long("level")
+            .takes_value(true)
+            .possible_values(&["1", "2", "3", "4", "5", "6", "7", "8", "9"])
+            .requires("OUTPUT")
+            .help("How hard to compress the file written with -o, from 1 (quickest to extract from) to 9 (smallest). 6 by default."))
+        .arg(clap::Arg::with_name("symbol-width")
+            .short("w")
+            .long("symbol-width")
+            .takes_value(true)
+            .possible_values(&["1", "2", "4"])
+            .help("Treats the input as little endian integers of this many bytes (e.g. token ids) instead of bytes."))
         .arg(clap::Arg::with_name("INPUT")
             .required(true)
+            .multiple(true)
             .index(1)
-            .help("Sets the file to factorize."))
+            .help("Sets the file to factorize. Several files are factorized together as separate documents."))
         .arg(clap::Arg::with_name("n-threads")
             .short("np")
             .help("Sets the number of threads to calculate with.")
             .takes_value(true)
-            .long("num-threads"));
+            .global(true)
+            .long("num-threads"))
+        .subcommand(clap::SubCommand::with_name("search")
+            .about("Finds every occurrence of the given patterns in a file.")
+            .arg(clap::Arg::with_name("count")
+                .short("c")
+                .long("count")
+                .help("Only print how many times each pattern occurs."))
+            .arg(clap::Arg::with_name("fm-index")
+                .short("i")
+                .long("fm-index")
+                .help("The input is an FM-index written by the index subcommand."))
+            .arg(clap::Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .conflicts_with("fm-index")
+                .help("Also writes the compressed file, from the same suffix array."))
+            .arg(clap::Arg::with_name("level")
+                .short("l")
+                .long("level")
+                .takes_value(true)
+                .possible_values(&["1", "2", "3", "4", "5", "6", "7", "8", "9"])
+                .requires("OUTPUT")
+                .help("How hard to compress the file written with -o, as for factorizing. 6 by default."))
+            .arg(clap::Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Sets the file to search."))
+            .arg(clap::Arg::with_name("PATTERN")
+                .required(true)
+                .multiple(true)
+                .index(2)
+                .help("The patterns to search for.")))
+        .subcommand(clap::SubCommand::with_name("bwt")
+            .about("Calculates the Burrows-Wheeler transform of a file, and estimates how well it compresses.")
+            .arg(clap::Arg::with_name("inverse")
+                .short("d")
+                .long("inverse")
+                .help("Undo a transform written with -o instead."))
+            .arg(clap::Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Writes the result to a file."))
+            .arg(clap::Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Sets the file to transform.")))
+        .subcommand(clap::SubCommand::with_name("index")
+            .about("Builds an FM-index of a file, which search can use with -i.")
+            .arg(clap::Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Writes the index to a file."))
+            .arg(clap::Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Sets the file to index.")))
+        .subcommand(clap::SubCommand::with_name("decompress")
+            .about("Decompresses a file written with -o.")
+            .arg(clap::Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Writes the result to a file instead of stdout."))
+            .arg(clap::Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Sets the file to decompress.")))
+        .subcommand(clap::SubCommand::with_name("extract")
+            .about("Decompresses just a range of bytes from a file written with -o.")
+            .arg(clap::Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Writes the result to a file instead of stdout."))
+            .arg(clap::Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Sets the file to extract from."))
+            .arg(clap::Arg::with_name("document")
+                .short("d")
+                .long("document")
+                .takes_value(true)
+                .conflicts_with_all(&["START", "LENGTH"])
+                .help("Extracts a whole document (counting from 0) instead of a range."))
+            .arg(clap::Arg::with_name("START")
+                .required_unless("document")
+                .index(2)
+                .help("Position of the first byte to extract."))
+            .arg(clap::Arg::with_name("LENGTH")
+                .required_unless("document")
+                .index(3)
+                .help("Number of bytes to extract.")))
+        .subcommand(clap::SubCommand::with_name("verify")
+            .about("Decompresses a file or archive in memory and checks it against its checksums.")
+            .alias("test")
+            .arg(clap::Arg::with_name("reference")
+                .short("r")
+                .long("reference")
+                .takes_value(true)
+                .help("Also compares the result against the original file."))
+            .arg(clap::Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Sets the file to verify.")))
+        .subcommand(clap::SubCommand::with_name("bench")
+            .about("Times every phase on synthetic corpora (or files) at several thread counts.")
+            .arg(clap::Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .help("Bytes in every generated corpus (4 MiB by default)."))
+            .arg(clap::Arg::with_name("corpus")
+                .short("c")
+                .long("corpus")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&["random", "low-entropy", "fibonacci", "repeated-blocks", "dna"])
+                .help("Only benchmarks this corpus (all of them by default, or none when files are given)."))
+            .arg(clap::Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .takes_value(true)
+                .use_delimiter(true)
+                .help("Comma separated thread counts to run with (1, 2, 4, ... up to the number of threads by default, or every count with --scaling)."))
+            .arg(clap::Arg::with_name("scaling")
+                .long("scaling")
+                .help("Reports every phase's speedup over one thread, and its efficiency (speedup over threads), instead of throughput."))
+            .arg(clap::Arg::with_name("runs")
+                .short("r")
+                .long("runs")
+                .takes_value(true)
+                .help("Runs every benchmark this many times and keeps the best time of every phase (3 by default)."))
+            .arg(clap::Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .help("Seeds the generated corpora."))
+            .arg(clap::Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "json", "csv"])
+                .help("Prints a table (text), a JSON object per line, or CSV."))
+            .arg(clap::Arg::with_name("FILE")
+                .multiple(true)
+                .index(1)
+                .help("Also benchmarks these files.")))
+        .subcommand(clap::SubCommand::with_name("sort")
+            .about("Sorts a file of integers in parallel with the radix sort.")
+            .arg(clap::Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["u32", "u64", "text"])
+                .help("Little endian u32s or u64s, or one integer per line (the default), i64 unless some only fit in a u64. The output is the same."))
+            .arg(clap::Arg::with_name("digit-width")
+                .long("digit-width")
+                .takes_value(true)
+                .possible_values(&["auto", "4", "8", "11", "16"])
+                .help("Sorts this many bits at a time while buckets are big enough for it, instead of picking by how big they are."))
+            .arg(clap::Arg::with_name("compare")
+                .long("compare")
+                .help("Also sorts with rayon's par_sort, checks they agree and compares the times."))
+            .arg(clap::Arg::with_name("stats")
+                .short("s")
+                .help("Print how long the sort took."))
+            .arg(clap::Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Writes the result to a file instead of stdout."))
+            .arg(clap::Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Sets the file to sort.")))
+        .subcommand(clap::SubCommand::with_name("archive")
+            .about("Packs files and directories into a single compressed archive, and back out again.")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("create")
+                .about("Archives files and directories, recursively.")
+                .arg(clap::Arg::with_name("solid")
+                    .long("solid")
+                    .help("Compresses every file together, so files can copy from each other."))
+                .arg(clap::Arg::with_name("level")
+                    .short("l")
+                    .long("level")
+                    .takes_value(true)
+                    .possible_values(&["1", "2", "3", "4", "5", "6", "7", "8", "9"])
+                    .help("How hard to compress, from 1 (quickest to extract from) to 9 (smallest). 6 by default."))
+                .arg(clap::Arg::with_name("ARCHIVE")
+                    .required(true)
+                    .index(1)
+                    .help("Sets the archive to write."))
+                .arg(clap::Arg::with_name("INPUT")
+                    .required(true)
+                    .multiple(true)
+                    .index(2)
+                    .help("The files and directories to archive.")))
+            .subcommand(clap::SubCommand::with_name("list")
+                .about("Lists what's in an archive.")
+                .arg(clap::Arg::with_name("ARCHIVE")
+                    .required(true)
+                    .index(1)
+                    .help("Sets the archive to list.")))
+            .subcommand(clap::SubCommand::with_name("extract")
+                .about("Extracts an archive.")
+                .arg(clap::Arg::with_name("directory")
+                    .short("C")
+                    .long("directory")
+                    .takes_value(true)
+                    .help("Extracts into this directory instead of the current one."))
+                .arg(clap::Arg::with_name("ARCHIVE")
+                    .required(true)
+                    .index(1)
+                    .help("Sets the archive to extract."))
+                .arg(clap::Arg::with_name("PATH")
+                    .multiple(true)
+                    .index(2)
+                    .help("Only extracts these files and directories."))));
 
     let matches = app.get_matches();
 
-    let filename = matches.value_of("INPUT").unwrap();
-    let stats_level = matches.occurrences_of("stats");
-    let should_print = matches.is_present("print");
     let num_threads_opt = matches.value_of("n-threads").map(|s| s.parse::<usize>());
 
-    let x: Result<(), Box<std::error::Error>> = (|| {
-
-
-        let start = std::time::Instant::now();
-
-        let mut f = std::fs::File::open(std::path::Path::new(&filename))?;
-        let mut buf = Vec::with_capacity(f.metadata().unwrap().len() as usize);
-        f.read_to_end(&mut buf)?;
-
-
-        let (lz, prev_occ) = match num_threads_opt {
+    let x: Result<(), Error> = (|| {
+        match num_threads_opt {
             Some(num_threads_parse) => {
 
                 let tp = rayon::Configuration::new()
                     .num_threads(num_threads_parse?)
-                    .build()?;
+                    .build()
+                    .map_err(|err| Error::ThreadPool(err.to_string()))?;
 
-                tp.install(|| lempel_ziv_77(buf.as_ref()).map_err(|box_err| box_err.to_string()))?
+                tp.install(|| run(&matches))?
             }
-            None => lempel_ziv_77(buf.as_ref())?
+            None => run(&matches)?
         };
-
-        let total_run_time = std::time::Instant::now() - start;
-
-        println!("{}", ansi_term::Color::Green.paint("<FINISHED>"));
-        if stats_level > 0 {
-            println!("Compressed {} bytes in {}s.", buf.len(), float_secs(total_run_time));
-
-            if stats_level > 1 {
-                println!("-- Finished phase `{}` in {}s", "Suffix Array", STATS.lock()?.get("esa_runtime").unwrap_or(&-1f64));
-                println!("-- Finished phase `{}` in {}s", "ANSV Arrays", STATS.lock()?.get("ansv_runtime").unwrap_or(&-1f64));
-                println!("-- Finished phase `{}` in {}s", "LPF Array", STATS.lock()?.get("lpf_runtime").unwrap_or(&-1f64));
-                println!("-- Finished phase `{}` in {}s", "LPF Array To LZ Array", STATS.lock()?.get("lpf_to_lz_runtime").unwrap_or(&-1f64));
-                println!();
-            }
-
-            println!("Approximate output length: {}", lz.len());
-            println!("Approximate reduction ratio: {}", (buf.len() as f64)/(lz.len() as f64));
-        }
-        if stats_level > 1 {
-            let average_reduction_factor = (lz.par_iter().zip(lz.par_iter().skip(1))
-                .map(|(&l, &r)| r - l).sum::<usize>() as f64)/(lz.len() as f64 - 1f64);
-            println!("Average pattern length: {}", average_reduction_factor);
-        }
-        if should_print {
-            println!("<FACTORIZATION>");
-            println!();
-            lz.iter().zip(prev_occ.iter()).zip(lz.iter().skip(1)).for_each(|((&lz_el, &prev_occ_el), &lz_next_el)| {
-                println!("{}, {} - {}", lz_el, prev_occ_el, lz_next_el - lz_el);
-            });
-        }
         Ok(())
     })();
 
     if let Err(err) = x {
-        println!("{}", ansi_term::Color::Red.paint(err.to_string()));
+        eprintln!("{}", ansi_term::Color::Red.paint(err.to_string()));
+        std::process::exit(err.exit_code());
     }
 
 }
@@ -225,11 +1139,46 @@ mod suffix_testing {
 
     }
 
+    #[test]
+    fn inverse_burrows_wheeler_errors_test() {
+        let with_primary = |primary: u64, transform: &[u8]| {
+            let mut buf = Vec::new();
+            utils::write_u64(&mut buf, primary).unwrap();
+            buf.extend_from_slice(transform);
+            super::inverse_burrows_wheeler(buf.as_ref())
+        };
+        assert_eq!(with_primary(4, b"annbaa").unwrap().as_ref(), b"banana".as_ref());
+
+        // Past the end of the transform.
+        assert_eq!(with_primary(999, b"annbaa").unwrap_err().exit_code(), 5);
+        // LF doesn't take row 0 through every row before reaching the primary one.
+        assert_eq!(with_primary(1, b"annbaa").unwrap_err().exit_code(), 5);
+        assert_eq!(with_primary(5000, utils::random_slice::<u8>(utils::DEFAULT_TEST_SIZE).as_ref()).unwrap_err().exit_code(), 5);
+        // Not even a primary index.
+        assert_eq!(super::inverse_burrows_wheeler(&[1, 2, 3]).unwrap_err().exit_code(), 4);
+    }
+
+    #[test]
+    fn empty_factorize_report_test() {
+        let (lz, _) = super::lempel_ziv_77(b"".as_ref(), &::profile::Profiler::new()).unwrap();
+        let report = super::factorize_report(0, 1, lz.as_ref(), None, &::profile::Profiler::new(), ::std::time::Duration::from_secs(0));
+        assert_eq!(report.get("phrases"), Some(&::stats::Value::Integer(0)));
+        assert_eq!(report.get("average_phrase_length"), Some(&::stats::Value::Missing));
+    }
+
+    #[test]
+    fn sort_values_test() {
+        let mut values = vec![5u64, u64::MAX, 0, 1 << 63, 5];
+        let (_, par_sort_time) = super::sort_values(values.as_mut_slice(), ::radix::DigitWidth::Auto, true).unwrap();
+        assert!(par_sort_time.is_some());
+        assert_eq!(values, vec![0, 5, 5, 1 << 63, u64::MAX]);
+    }
+
     #[bench]
     fn lempel_ziv_77_bench(bencher: &mut test::Bencher) {
         let data = utils::random_slice::<u8>(utils::BENCH_SIZE);
         bencher.iter(|| {
-            super::lempel_ziv_77(data.as_ref());
+            super::lempel_ziv_77(data.as_ref(), &::profile::Profiler::new())
         })
     }
 }
@@ -0,0 +1,221 @@
+use std;
+use rayon;
+use utils::float_secs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Explanation:
+// - Phases:
+//     Every stage of the pipeline runs inside phase(name, ...), which times it. The same name can come up more
+// than once (e.g. once per file in an archive), in which case phases() adds the times up.
+//
+// - Memory:
+//     What we track is the intermediate arrays, not every allocation: the pipeline tells us when it makes
+// or drops one, and we keep the total of what's alive and the most that ever was at once. The arrays are all
+// O(n) words and everything else is small next to them, so the peak is close to the real high water mark.
+//
+// - Work balance:
+//     The chunked phases (ANSV, LPF) run every chunk through chunk(phase, len, ...), which notes which thread
+// ran it, how many elements it had and how long it took. Adding those up per thread says whether the work was
+// split evenly: the busiest thread over the average, which is 1 when it's perfect. The average is over every
+// thread in the pool, not just the ones that got a chunk, so a phase that ran on one thread of eight comes out
+// as 8. rayon doesn't tell us the index of the thread we're on, so threads are numbered in the order they
+// first show up.
+//
+// The locks are taken once per phase and once per chunk (about 3 per thread), so none of this costs anything
+// next to the phases themselves.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Phase {
+    pub name: &'static str,
+    pub duration: Duration
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadWork {
+    pub thread: usize,
+    pub chunks: usize,
+    pub elements: usize,
+    pub busy: Duration
+}
+
+struct Chunk {
+    phase: &'static str,
+    thread: usize,
+    // Threads in the pool it ran in.
+    pool_threads: usize,
+    len: usize,
+    duration: Duration
+}
+
+pub struct Profiler {
+    phases: Mutex<Vec<Phase>>,
+    chunks: Mutex<Vec<Chunk>>,
+    threads: Mutex<Vec<std::thread::ThreadId>>,
+    // (live, peak) bytes.
+    memory: Mutex<(usize, usize)>
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            phases: Mutex::new(Vec::new()),
+            chunks: Mutex::new(Vec::new()),
+            threads: Mutex::new(Vec::new()),
+            memory: Mutex::new((0, 0))
+        }
+    }
+
+    pub fn phase<R, F: FnOnce() -> R>(&self, name: &'static str, f: F) -> R {
+        let start = Instant::now();
+        let result = f();
+        let duration = Instant::now() - start;
+        self.phases.lock().unwrap().push(Phase { name, duration });
+        result
+    }
+
+    fn thread_number(&self) -> usize {
+        let id = std::thread::current().id();
+        let mut threads = self.threads.lock().unwrap();
+        match threads.iter().position(|&thread| thread == id) {
+            Some(thread) => thread,
+            None => {
+                threads.push(id);
+                threads.len() - 1
+            }
+        }
+    }
+
+    pub fn chunk<R, F: FnOnce() -> R>(&self, phase: &'static str, len: usize, f: F) -> R {
+        let thread = self.thread_number();
+        let start = Instant::now();
+        let result = f();
+        let duration = Instant::now() - start;
+        let pool_threads = rayon::current_num_threads();
+        self.chunks.lock().unwrap().push(Chunk { phase, thread, pool_threads, len, duration });
+        result
+    }
+
+    pub fn allocated_bytes(&self, bytes: usize) {
+        let mut memory = self.memory.lock().unwrap();
+        memory.0 += bytes;
+        memory.1 = memory.1.max(memory.0);
+    }
+
+    pub fn freed_bytes(&self, bytes: usize) {
+        let mut memory = self.memory.lock().unwrap();
+        memory.0 -= bytes.min(memory.0);
+    }
+
+    pub fn allocated<T>(&self, array: &[T]) {
+        self.allocated_bytes(std::mem::size_of_val(array));
+    }
+
+    pub fn freed<T>(&self, array: &[T]) {
+        self.freed_bytes(std::mem::size_of_val(array));
+    }
+
+    pub fn peak_bytes(&self) -> usize {
+        self.memory.lock().unwrap().1
+    }
+
+    // Every phase in the order it first ran, with the times of repeats added up.
+    pub fn phases(&self) -> Vec<Phase> {
+        let mut merged = Vec::<Phase>::new();
+        for phase in self.phases.lock().unwrap().iter() {
+            match merged.iter_mut().find(|merged_phase| merged_phase.name == phase.name) {
+                Some(merged_phase) => merged_phase.duration += phase.duration,
+                None => merged.push(*phase)
+            }
+        }
+        merged
+    }
+
+    pub fn phase_duration(&self, name: &str) -> Option<Duration> {
+        self.phases().iter().find(|phase| phase.name == name).map(|phase| phase.duration)
+    }
+
+    // What every thread that ran any of the phase's chunks did, by thread.
+    pub fn thread_work(&self, phase: &str) -> Vec<ThreadWork> {
+        let mut work = Vec::<ThreadWork>::new();
+        for chunk in self.chunks.lock().unwrap().iter().filter(|chunk| chunk.phase == phase) {
+            if work.iter().all(|thread_work| thread_work.thread != chunk.thread) {
+                work.push(ThreadWork { thread: chunk.thread, chunks: 0, elements: 0, busy: Duration::new(0, 0) });
+            }
+            let thread_work = work.iter_mut().find(|thread_work| thread_work.thread == chunk.thread).unwrap();
+            thread_work.chunks += 1;
+            thread_work.elements += chunk.len;
+            thread_work.busy += chunk.duration;
+        }
+        work.sort_by_key(|thread_work| thread_work.thread);
+        work
+    }
+
+    // The busiest thread's time over the average across the pool, or None if the phase had no chunks.
+    pub fn imbalance(&self, phase: &str) -> Option<f64> {
+        let work = self.thread_work(phase);
+        let busy = work.iter().map(|thread_work| float_secs(thread_work.busy)).collect::<Vec<f64>>();
+        let total = busy.iter().sum::<f64>();
+        if work.is_empty() || total == 0f64 {
+            return None;
+        }
+        // Threads that never got a chunk did no work, but they still count towards the average.
+        let pool_threads = self.chunks.lock().unwrap().iter().filter(|chunk| chunk.phase == phase)
+            .map(|chunk| chunk.pool_threads).max().unwrap_or(0).max(work.len());
+        Some(busy.iter().cloned().fold(0f64, f64::max) / (total / pool_threads as f64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std;
+    use rayon;
+    use rayon::prelude::*;
+    use super::Profiler;
+
+    #[test]
+    fn memory_test() {
+        let profiler = Profiler::new();
+        let a = vec![0u64; 100];
+        let b = vec![0u8; 50];
+        profiler.allocated(a.as_ref());
+        profiler.allocated(b.as_ref());
+        profiler.freed(a.as_ref());
+        profiler.allocated(b.as_ref());
+        assert_eq!(profiler.peak_bytes(), 850);
+    }
+
+    #[test]
+    fn phases_test() {
+        let profiler = Profiler::new();
+        assert_eq!(profiler.phase("a", || 1), 1);
+        profiler.phase("b", || ());
+        profiler.phase("a", || ());
+        let names = profiler.phases().iter().map(|phase| phase.name).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["a", "b"]);
+        assert!(profiler.phase_duration("c").is_none());
+    }
+
+    #[test]
+    fn thread_work_test() {
+        let profiler = Profiler::new();
+        let data = vec![1u64; 1 << 16];
+        let sum = data.par_chunks(1000).map(|chunk| {
+            profiler.chunk("sum", chunk.len(), || chunk.iter().sum::<u64>())
+        }).sum::<u64>();
+        assert_eq!(sum, data.len() as u64);
+        let work = profiler.thread_work("sum");
+        assert_eq!(work.iter().map(|thread_work| thread_work.elements).sum::<usize>(), data.len());
+        assert_eq!(work.iter().map(|thread_work| thread_work.chunks).sum::<usize>(), data.len().div_ceil(1000));
+        assert!(profiler.thread_work("other").is_empty());
+        assert!(profiler.imbalance("other").is_none());
+    }
+
+    #[test]
+    fn idle_threads_imbalance_test() {
+        let pool = rayon::Configuration::new().num_threads(4).build().unwrap();
+        let profiler = Profiler::new();
+        pool.install(|| profiler.chunk("serial", 1, || std::thread::sleep(std::time::Duration::from_millis(1))));
+        assert_eq!(profiler.imbalance("serial"), Some(4f64));
+    }
+}
@@ -1,224 +1,129 @@
-use utils;
 use rayon::prelude::*;
-use std;
-
-// The purpose of this is to allow things which are constant for all sorted members
-// in calculating their bucket to be precomputed..
-// the idea was to precompute which sub_byte should be considered for [u8; 3]s
-// but reading that data from a struct *slowed things down*. Very frustrating!!
-// There appears to be some cost for making this all abstract too.. also frustrating!!
-
-pub trait RadixPrecompute {
-    type StepConsts: Sync = ();
-    fn get_step_consts(cur_steps: usize) -> Self::StepConsts;
-}
-
-pub trait Radix where Self: RadixPrecompute {
-    const N_BUCKETS: usize;
-    const N_STEPS: usize;
-
-    // The step consts must be read only because this function is intended to be run
-    // highly in parallel. So we cannot achieve a mutable borrow because you can only have
-    // one of those at once, whereas we can have as many read only borrows as we want
-    // at a given time. So this must be a plain reference, not &mut, although
-    // it would be nice if there is ever a way to make this &mut.
-    fn get_bucket(self: &Self, cur_step: usize, step_consts: &Self::StepConsts) -> usize;
-
-
-    const HISTOGRAM_BATCH_SIZE: usize = 4096 * 32;
-    const HISTOGRAM_PARALLEL_BATCH_COUNT: usize = 4;
 
+// Explanation:
+// - Keys and digits:
+//     Anything Radix gives a key, an unsigned number whose order is the order to sort in, of which only the
+// low KEY_BITS bits are ever set. We sort on its digits most significant first, splitting into a bucket per
+// digit value and going on with the next digit inside every bucket (in parallel when it's big enough).
+//
+// - Digit width:
+//     A wider digit means fewer passes over the data, but a pass has 2^width buckets to count and scatter
+// into, which only pays off when there are plenty of elements for every bucket. DigitWidth::Auto picks the
+// width for every bucket on its own: the widest of 16, 11, 8 and 4 bits that leaves AUTO_ELEMENTS_PER_BUCKET
+// elements a bucket on average, so a 100M element sort starts with 16 bit digits and its buckets finish with
+// narrower ones. A fixed width is used for as long as a bucket is big enough for it by the same rule, and
+// buckets smaller than that go on with the width Auto would pick, so a 16 bit width doesn't count 65536
+// buckets for every bucket of a few hundred elements it leaves behind. A width that doesn't divide the key
+// just makes the last digit narrower.
+//
+// - Where things end up:
+//     Every pass moves a bucket from one buffer to the other, and with a width per bucket the number of
+// passes isn't known up front, so each step is told which of the two buffers its bucket has to end up in
+// and the buckets that stop early (they ran out of bits, or got small enough for a comparison sort) copy
+// themselves over if they need to.
+
+pub trait Radix {
+    const KEY_BITS: usize;
+
+    fn radix_key(&self) -> u64;
+
+    // Buckets bigger than this have their own buckets sorted in parallel.
     const RADIX_PARALLEL_EL_COUNT: usize = 128;
+
+    // Buckets this small are finished off with a comparison sort on their keys, since a pass costs 2^width
+    // no matter how few elements there are.
+    const COMPARISON_SORT_EL_COUNT: usize = 32;
 }
 
 mod radix_byte_triple {
-    impl super::RadixPrecompute for [u8; 3] {
-        type StepConsts = ();
-        fn get_step_consts(_cur_step: usize) -> () {
-            ()
-        }
-    }
-
     impl super::Radix for [u8; 3] {
-
-        const N_BUCKETS: usize = 16;
-        const N_STEPS: usize = 2 * 3;
+        const KEY_BITS: usize = 24;
 
         #[inline(always)]
-        fn get_bucket(self: &[u8; 3], cur_step: usize, _step_consts: &()) -> usize {
-            let even_phase = 1-(cur_step % 2) as u8;
-            let sub_index = cur_step/2;
-            let byte = self[sub_index];
-            (((byte >> even_phase * 4) & 0b00001111) as usize)
+        fn radix_key(self: &[u8; 3]) -> u64 {
+            (self[0] as u64) << 16 | (self[1] as u64) << 8 | self[2] as u64
         }
     }
 }
 
-fn radix_indices_sort_step<'a, T, F>(data: &'a [T], indices: &'a mut [usize], indices_swap: &'a mut [usize],
-    current_step_num: usize,
-    counts: &mut [usize], counts_before: &mut [usize],
-    get_bucket: &F)
-    where T: 'a,
-    F: Fn(&'a [T], usize, usize) -> usize {
-
-    let elements_received = indices.len();
-
-    counts.iter_mut().for_each(|v| {
-        *v = 0;
-    });
-    for i in 0..elements_received {
-        counts[get_bucket(data, indices[i], current_step_num)] += 1;
-    }
-
-    counts_before[0] = 0;
-    for i in 1..counts_before.len() {
-        counts_before[i] = counts_before[i-1] + counts[i-1];
+// Signed integers have their sign bit flipped so the negative ones come first, and usize and isize are
+// sorted as 64 bits wide whatever they really are.
+mod radix_integers {
+    macro_rules! radix_integer {
+        ($($t:ty, $n_bytes:expr, $sign_bit:expr);*) => {$(
+            impl super::Radix for $t {
+                const KEY_BITS: usize = 8 * $n_bytes;
+
+                #[inline(always)]
+                fn radix_key(self: &$t) -> u64 {
+                    ((*self as u64) ^ $sign_bit) & (u64::MAX >> (64 - 8 * $n_bytes))
+                }
+            }
+        )*}
     }
 
-    for i in 0..elements_received {
-        let bucket_pos = get_bucket(data, indices[i], current_step_num);
-        indices_swap[counts_before[bucket_pos]] = indices[i];
-        counts_before[bucket_pos] += 1;
-    }
+    radix_integer!(u8, 1, 0; u16, 2, 0; u32, 4, 0; u64, 8, 0; usize, 8, 0;
+                   i8, 1, 1 << 7; i16, 2, 1 << 15; i32, 4, 1 << 31; i64, 8, 1 << 63; isize, 8, 1 << 63);
 }
-fn radix_indices_sort_recursive_manager_step<T, F>(data: & [T],
-                                                    indices: & mut [usize], indices_swap: & mut [usize],
-                                                    current_step_num: usize,
-                                                    counts: &mut [usize], counts_before: &mut [usize],
-                                                    n_buckets: usize, n_steps: usize, get_bucket: &F)
-    where T: Sync,
-          F: Fn(& [T], usize, usize) -> usize + Sync + Send
-{
-
-    const PARALLEL_ELEMENT_COUNT: usize = 128;
 
-    radix_indices_sort_step(data, indices, indices_swap, current_step_num, counts, counts_before, get_bucket);
+const AUTO_ELEMENTS_PER_BUCKET: usize = 16;
 
-    if current_step_num == n_steps - 1 {
-        return;
-    }
-
-    let elements_received: usize = indices.len();
-
-    if elements_received <= 1 {
-        return;
-    }
-
-    let mut sub_slices = Vec::<(&mut [usize], &mut [usize])>::with_capacity(n_buckets);
-
-    let mut remaining_indices = indices;
-    let mut remaining_indices_swap = indices_swap;
-
-    let mut last_split_index = 0usize;
-    for last_filled_index in counts_before.iter() {
-        if *last_filled_index != last_split_index {
-
-            let old_remaining_indices = remaining_indices;
-            let old_remaining_indices_swap = remaining_indices_swap;
-
-            let (indices_tmp, new_indices) = old_remaining_indices.split_at_mut(last_filled_index - last_split_index);
-            let (indices_swap_tmp, new_indices_swap) = old_remaining_indices_swap.split_at_mut(last_filled_index - last_split_index);
-
-            remaining_indices = new_indices;
-            remaining_indices_swap = new_indices_swap;
-
-            sub_slices.push((indices_tmp, indices_swap_tmp));
-
-            last_split_index = *last_filled_index;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigitWidth {
+    Auto,
+    Bits4,
+    Bits8,
+    Bits11,
+    Bits16
+}
 
+impl DigitWidth {
+    pub fn from_bits(bits: usize) -> Option<DigitWidth> {
+        match bits {
+            4 => Some(DigitWidth::Bits4),
+            8 => Some(DigitWidth::Bits8),
+            11 => Some(DigitWidth::Bits11),
+            16 => Some(DigitWidth::Bits16),
+            _ => None
         }
     }
 
-    if elements_received <= PARALLEL_ELEMENT_COUNT {
-        sub_slices.iter_mut()
-            .for_each(|&mut (ref mut sub_indices, ref mut sub_indices_swap)| {
-                radix_indices_sort_recursive_manager_step(data, *sub_indices_swap, *sub_indices,
-                                                          current_step_num+1,
-                                                          counts, counts_before,
-                                                          n_buckets, n_steps, get_bucket);
-            });
-
-    } else {
-        sub_slices.par_iter_mut()
-            .for_each(|&mut (ref mut sub_indices, ref mut sub_indices_swap)| {
-                radix_indices_sort_recursive_manager_step(data, *sub_indices_swap, *sub_indices,
-                                                          current_step_num+1,
-                                                          vec![0; n_buckets].as_mut_slice(), vec![0; n_buckets].as_mut_slice(),
-                                                          n_buckets, n_steps, get_bucket);
-            });
-    }
-}
-pub fn par_radix_indices_sort<T, F>(data: &[T], indices: & mut [usize], n_buckets: usize, n_steps: usize, get_bucket: &F)
-    where T: Sync,
-          F: Fn(&[T], usize, usize) -> usize + Sync + Send
-{
-    let mut indices_swap = vec![0; indices.len()];
-
-    radix_indices_sort_recursive_manager_step(data,indices, indices_swap.as_mut_slice(),
-                                              0,
-                                              vec![0; n_buckets].as_mut_slice(),vec![0; n_buckets].as_mut_slice(),
-                                              n_buckets, n_steps, get_bucket);
-    if n_steps % 2 == 1 {
-        indices.copy_from_slice(indices_swap.as_mut_slice());
+    // How many bits to sort a bucket of len elements on at once.
+    fn bits_for(&self, len: usize) -> usize {
+        let bits = match *self {
+            DigitWidth::Auto => 0,
+            DigitWidth::Bits4 => 4,
+            DigitWidth::Bits8 => 8,
+            DigitWidth::Bits11 => 11,
+            DigitWidth::Bits16 => 16
+        };
+        if bits != 0 && len >> bits >= AUTO_ELEMENTS_PER_BUCKET {
+            return bits;
+        }
+        [16, 11, 8].iter().cloned()
+            .find(|&bits| len >> bits >= AUTO_ELEMENTS_PER_BUCKET)
+            .unwrap_or(4)
     }
 }
 
-pub fn par_radix_triplet_indices_sort(data: &[u8], indices: &mut [usize]) {
-    par_radix_indices_sort(data, indices, 16, 6, &|data , index, current_step| {
-        let even_phase = 1-(current_step % 2) as u8;
-        let sub_index = current_step/2;
-        let byte = data[index + sub_index];
-        (((byte >> even_phase * 4) & 0b00001111) as usize)
-    })
-}
-
+// Moves data into data_swap sorted on the digit at shift, stably. The digit is as wide as counts has buckets
+// for, so counts and counts_before need a power of two length.
+// The shift and mask are worked out once, out here: reading per step constants out of a struct for every
+// element (what an earlier version did to pick the nibble of a [u8; 3]) *slowed things down*.
 fn radix_step<'a, T, G>(data: &'a mut [T], data_swap: &'a mut [T],
                   carry: &'a mut [G], carry_swap: &'a mut [G],
-                  current_step_num: usize,
+                  shift: usize,
                   counts: &mut [usize], counts_before: &mut [usize])
-where T:'a + self::Radix + Copy + Sync + std::fmt::Debug,
+where T:'a + self::Radix + Copy + Sync,
     G: 'a + Copy + Sync
 {
 
-    let batch_size: usize = T::HISTOGRAM_BATCH_SIZE;
-//    let parallel_batch_count: usize = T::HISTOGRAM_PARALLEL_BATCH_COUNT;
-
-    let step_consts = T::get_step_consts(current_step_num);
-
+    let mask = counts.len() as u64 - 1;
     let elements_received = data.len();
 
-    let batches =  utils::calc_n_chunks(elements_received, batch_size);
-
-    // Writing anything but `true` here (such as the commented condition following it)
-   if true { //batches <= parallel_batch_count {
-        counts.iter_mut().for_each(|v| {
-            *v = 0;
-        });
-        for i in 0..elements_received {
-            counts[data[i].get_bucket(current_step_num, &step_consts)] += 1;
-        }
-    } else {
-
-//        decision remains to be made if manually batching is faster in general than rayon's built in fold batching
-//        from benchmarks it seems to be nearly the same speed .. if we don't gain much off manually batching
-//        using rayon's is better.. it makes the code simpler and may get faster over time with improvements to
-//        rayon.. so don't delete this code yet!
-
-        counts.copy_from_slice(&((0..batches).into_par_iter()
-            .map(|batch| {
-                let mut sub_counts = vec![0usize; <T as Radix>::N_BUCKETS];
-                for i in batch* batch_size..std::cmp::min((batch+1)* batch_size, elements_received) {
-                    sub_counts[data[i].get_bucket(current_step_num, &step_consts)] += 1;
-                }
-                sub_counts
-            })
-            .reduce(|| vec![0usize; T::N_BUCKETS], |mut a, b| {
-                for i in 0..T::N_BUCKETS {
-                    a[i] += b[i];
-                }
-                a
-            })).as_slice() as &[usize]);
-
+    counts.iter_mut().for_each(|v| *v = 0);
+    for i in 0..elements_received {
+        counts[((data[i].radix_key() >> shift) & mask) as usize] += 1;
     }
 
     counts_before[0] = 0;
@@ -227,7 +132,7 @@ where T:'a + self::Radix + Copy + Sync + std::fmt::Debug,
     }
 
     for i in 0..elements_received {
-        let bucket_pos = data[i].get_bucket(current_step_num, &step_consts);
+        let bucket_pos = ((data[i].radix_key() >> shift) & mask) as usize;
         data_swap[counts_before[bucket_pos]] = data[i];
         carry_swap[counts_before[bucket_pos]] = carry[i];
         counts_before[bucket_pos] += 1;
@@ -235,30 +140,56 @@ where T:'a + self::Radix + Copy + Sync + std::fmt::Debug,
 
 }
 
+// Sorts a small bucket by its keys (stably, like the radix steps) into whichever buffer it has to end up in.
+fn comparison_sort_step<'a, T, G>(data: &'a mut [T], data_swap: &'a mut [T],
+                                  carry: &'a mut [G], carry_swap: &'a mut [G],
+                                  into_swap: bool)
+    where T: 'a + Radix + Copy,
+          G: 'a + Copy
+{
+    let mut order = (0..data.len()).collect::<Vec<usize>>();
+    order.sort_by(|&a, &b| data[a].radix_key().cmp(&data[b].radix_key()));
+    for (i, &from) in order.iter().enumerate() {
+        data_swap[i] = data[from];
+        carry_swap[i] = carry[from];
+    }
+    if !into_swap {
+        data.copy_from_slice(data_swap);
+        carry.copy_from_slice(carry_swap);
+    }
+}
 
+// Sorts data on the low remaining_bits bits of its keys (the ones above are all the same), leaving the
+// result in data_swap if into_swap is set and in data otherwise.
 fn radix_recursive_manager_step<'a, T, G>(data: &'a mut [T], data_swap: &'a mut [T],
                                     carry: &'a mut [G], carry_swap: &'a mut [G],
-                                    current_step_num: usize,
-                                    counts: &mut [usize], counts_before: &mut [usize])
-    where T: 'a + Radix + Copy + Sync + Send + std::fmt::Debug,
+                                    remaining_bits: usize, digit_width: DigitWidth, into_swap: bool)
+    where T: 'a + Radix + Copy + Sync + Send,
         G: 'a + Copy + Sync + Send
 {
 
-    const PARALLEL_ELEMENT_COUNT: usize = 128;
-
-    radix_step(data, data_swap, carry, carry_swap,current_step_num, counts, counts_before);
+    let elements_received: usize = data.len();
 
-    if current_step_num == T::N_STEPS - 1 {
+    if remaining_bits == 0 {
+        if into_swap {
+            data_swap.copy_from_slice(data);
+            carry_swap.copy_from_slice(carry);
+        }
         return;
     }
 
-    let elements_received: usize = data.len();
-
-    if elements_received <= 1 {
+    if elements_received <= T::COMPARISON_SORT_EL_COUNT {
+        comparison_sort_step(data, data_swap, carry, carry_swap, into_swap);
         return;
     }
 
-    let mut sub_slices = Vec::<(&mut [T], &mut [T], &mut [G], &mut [G])>::with_capacity(T::N_BUCKETS);
+    let bits = digit_width.bits_for(elements_received).min(remaining_bits);
+    let shift = remaining_bits - bits;
+    let mut counts = vec![0; 1 << bits];
+    let mut counts_before = vec![0; 1 << bits];
+    radix_step(data, data_swap, carry, carry_swap, shift, counts.as_mut_slice(), counts_before.as_mut_slice());
+
+    let mut sub_slices = Vec::<(&mut [T], &mut [T], &mut [G], &mut [G])>::with_capacity(1 << bits);
 
     let mut remaining_data = data;
     let mut remaining_data_swap = data_swap;
@@ -295,13 +226,13 @@ fn radix_recursive_manager_step<'a, T, G>(data: &'a mut [T], data_swap: &'a mut
         }
     }
 
-    if elements_received <= PARALLEL_ELEMENT_COUNT {
+    // The buckets are in data_swap now, so for them the buffers swap places, and so does where they end up.
+    if elements_received <= T::RADIX_PARALLEL_EL_COUNT {
         sub_slices.iter_mut()
             .for_each(|&mut (ref mut sub_data, ref mut sub_data_swap, ref mut sub_carry, ref mut sub_carry_swap)| {
                 radix_recursive_manager_step(*sub_data_swap, *sub_data,
                                              *sub_carry_swap, *sub_carry,
-                                             current_step_num+1,
-                                             counts, counts_before);
+                                             shift, digit_width, !into_swap);
             });
 
     } else {
@@ -309,28 +240,200 @@ fn radix_recursive_manager_step<'a, T, G>(data: &'a mut [T], data_swap: &'a mut
             .for_each(|&mut (ref mut sub_data, ref mut sub_data_swap, ref mut sub_carry, ref mut sub_carry_swap)| {
                 radix_recursive_manager_step(*sub_data_swap, *sub_data,
                                              *sub_carry_swap, *sub_carry,
-                                             current_step_num+1,
-                                             vec![0; T::N_BUCKETS].as_mut_slice(),vec![0; T::N_BUCKETS].as_mut_slice());
+                                             shift, digit_width, !into_swap);
             });
     }
 }
 
+// Sorts indices by get_key(data, index), of which only the low key_bits bits may be set. The keys are worked
+// out once up front, so every pass reads them in order rather than jumping around data. Only the tests use it
+// so far.
+#[allow(dead_code)]
+pub fn par_radix_indices_sort<T, F>(data: &[T], indices: &mut [usize], key_bits: usize, digit_width: DigitWidth, get_key: &F)
+    where T: Sync,
+          F: Fn(&[T], usize) -> u64 + Sync + Send
+{
+    let mut keys = indices.par_iter().map(|&index| get_key(data, index)).collect::<Vec<u64>>();
+    let mut keys_swap = keys.clone();
+    let mut indices_swap = indices.to_vec();
+
+    radix_recursive_manager_step(keys.as_mut_slice(), keys_swap.as_mut_slice(),
+                                 indices, indices_swap.as_mut_slice(),
+                                 key_bits, digit_width, false);
+}
+
+// For the commented out DC3 sketch in suffix.rs, nothing calls it for real yet.
+#[allow(dead_code)]
+pub fn par_radix_triplet_indices_sort(data: &[u8], indices: &mut [usize]) {
+    par_radix_indices_sort(data, indices, 24, DigitWidth::Auto, &|data, index| {
+        (data[index] as u64) << 16 | (data[index + 1] as u64) << 8 | data[index + 2] as u64
+    })
+}
+
+// Sorts data, the keys, and moves carry[i] (the value) to wherever data[i] goes. The sort is stable: keys that
+// are equal keep their values in the order they came in, so e.g. sorting by a secondary key and then by the
+// primary one sorts by both. Pass a slice of () as the carry to sort just the keys.
 pub fn par_radix_sort<'a, T, G>(data: &'a mut [T], carry: &'a mut [G])
-    where T:'a + Radix + Copy + Sync + Send + Clone + Default + std::fmt::Debug,
+    where T:'a + Radix + Copy + Sync + Send,
     G: 'a + Copy + Sync + Send
 {
-    let mut data_swap = vec![<T>::default(); data.len()];
+    par_radix_sort_with_digit_width(data, carry, DigitWidth::Auto);
+}
+
+pub fn par_radix_sort_with_digit_width<'a, T, G>(data: &'a mut [T], carry: &'a mut [G], digit_width: DigitWidth)
+    where T:'a + Radix + Copy + Sync + Send,
+    G: 'a + Copy + Sync + Send
+{
+    assert_eq!(data.len(), carry.len(), "Every key needs exactly one value to carry.");
+    let mut data_swap = data.to_vec();
     let mut carry_swap = carry.to_vec();
 
     radix_recursive_manager_step(data, data_swap.as_mut_slice(),
                                  carry, carry_swap.as_mut_slice(),
-                                 0,
-                                 vec![0; T::N_BUCKETS].as_mut_slice(), vec![0; T::N_BUCKETS].as_mut_slice());
-    if T::N_STEPS % 2 == 1 {
-        data.copy_from_slice(data_swap.as_mut_slice());
+                                 T::KEY_BITS, digit_width, false);
+}
+
+// par_radix_sort for (key, value) records in one slice. The CLI only ever sorts bare keys.
+#[allow(dead_code)]
+pub fn par_radix_sort_pairs<K, V>(pairs: &mut [(K, V)])
+    where K: Radix + Copy + Sync + Send,
+          V: Copy + Sync + Send
+{
+    par_radix_sort_by_key(pairs, |&(key, _)| key);
+}
+
+// Byte strings of any length, most significant byte first. Bucket 0 is for strings that have already ended,
+// so a string comes before everything it's a prefix of. Strings can share long prefixes (e.g. suffixes of a
+// repetitive text), so when everything lands in one bucket we go on to the next byte in a loop rather than
+// recursing, and small buckets are finished off with a comparison sort from the current depth on.
+const STRING_N_BUCKETS: usize = 257;
+const STRING_COMPARISON_SORT_EL_COUNT: usize = 32;
+const STRING_PARALLEL_EL_COUNT: usize = 1024;
+
+#[inline(always)]
+fn string_bucket(string: &[u8], depth: usize) -> usize {
+    string.get(depth).map_or(0, |&byte| byte as usize + 1)
+}
+
+fn strings_sort_recursive_step<'a, F>(indices: &mut [usize], indices_swap: &mut [usize], depth: usize, get_string: &F)
+    where F: Fn(usize) -> &'a [u8] + Sync
+{
+    let mut indices = indices;
+    let mut indices_swap = indices_swap;
+    let mut depth = depth;
+    let mut counts = [0usize; STRING_N_BUCKETS];
+    loop {
+        let elements_received = indices.len();
+        if elements_received <= STRING_COMPARISON_SORT_EL_COUNT {
+            indices.sort_by(|&a, &b| get_string(a)[depth..].cmp(&get_string(b)[depth..]));
+            return;
+        }
+        counts.iter_mut().for_each(|v| *v = 0);
+        for &index in indices.iter() {
+            counts[string_bucket(get_string(index), depth)] += 1;
+        }
+        if counts[0] == elements_received {
+            return;
+        }
+        if counts.iter().all(|&count| count == 0 || count == elements_received) {
+            depth += 1;
+            continue;
+        }
+
+        let mut counts_before = [0usize; STRING_N_BUCKETS];
+        for i in 1..STRING_N_BUCKETS {
+            counts_before[i] = counts_before[i - 1] + counts[i - 1];
+        }
+        for &index in indices.iter() {
+            let bucket = string_bucket(get_string(index), depth);
+            indices_swap[counts_before[bucket]] = index;
+            counts_before[bucket] += 1;
+        }
+        indices.copy_from_slice(indices_swap);
+
+        // The strings that ended are all equal, so bucket 0 is done already, and so is any bucket of one.
+        let mut sub_slices = Vec::<(&mut [usize], &mut [usize])>::with_capacity(STRING_N_BUCKETS);
+        let mut remaining_indices = indices;
+        let mut remaining_indices_swap = indices_swap;
+        for (bucket, &count) in counts.iter().enumerate() {
+            let (bucket_indices, new_indices) = { remaining_indices }.split_at_mut(count);
+            let (bucket_indices_swap, new_indices_swap) = { remaining_indices_swap }.split_at_mut(count);
+            remaining_indices = new_indices;
+            remaining_indices_swap = new_indices_swap;
+            if bucket != 0 && count > 1 {
+                sub_slices.push((bucket_indices, bucket_indices_swap));
+            }
+        }
+
+        // Only the buckets other than the biggest one get a call of their own, and they're at most half of
+        // what we got, so the stack stays logarithmic even when every step only peels off a string or two
+        // (like the suffixes of a long run of one byte). The biggest one is sorted by going around again.
+        let biggest = match (0..sub_slices.len()).max_by_key(|&i| sub_slices[i].0.len()) {
+            Some(biggest) => biggest,
+            None => return
+        };
+        let (biggest_indices, biggest_indices_swap) = sub_slices.swap_remove(biggest);
+        if elements_received <= STRING_PARALLEL_EL_COUNT {
+            sub_slices.into_iter().for_each(|(sub_indices, sub_indices_swap)| {
+                strings_sort_recursive_step(sub_indices, sub_indices_swap, depth + 1, get_string);
+            });
+        } else {
+            sub_slices.into_par_iter().for_each(|(sub_indices, sub_indices_swap)| {
+                strings_sort_recursive_step(sub_indices, sub_indices_swap, depth + 1, get_string);
+            });
+        }
+        indices = biggest_indices;
+        indices_swap = biggest_indices_swap;
+        depth += 1;
     }
 }
 
+// Sorts indices by the byte strings get_string gives for them, e.g. |i| &data[i..] to sort suffixes, or
+// |i| &data[i..(i + k).min(data.len())] for just their first k bytes. Equal strings stay in the order they
+// were in.
+pub fn par_radix_strings_indices_sort<'a, F>(indices: &mut [usize], get_string: &F)
+    where F: Fn(usize) -> &'a [u8] + Sync
+{
+    let mut indices_swap = vec![0; indices.len()];
+    strings_sort_recursive_step(indices, indices_swap.as_mut_slice(), 0, get_string);
+}
+
+// Sorts byte strings (or anything that can be borrowed as them, like lines of a file). The CLI has no strings
+// to sort, so for now only the tests call this.
+#[allow(dead_code)]
+pub fn par_radix_strings_sort<S: AsRef<[u8]> + Sync>(strings: &mut [S]) {
+    let mut order = (0..strings.len()).collect::<Vec<usize>>();
+    {
+        let borrowed = &*strings;
+        par_radix_strings_indices_sort(order.as_mut_slice(), &|index| borrowed[index].as_ref());
+    }
+    // Puts strings[order[i]] at i, one cycle of the permutation at a time.
+    let mut placed = vec![false; strings.len()];
+    for start in 0..strings.len() {
+        let mut i = start;
+        while !placed[i] {
+            placed[i] = true;
+            if order[i] == start {
+                break;
+            }
+            strings.swap(i, order[i]);
+            i = order[i];
+        }
+    }
+}
+
+// Sorts anything by a key that can be radix sorted, e.g. structs by one of their fields. Elements with the
+// same key stay in the order they were in. Nothing in the CLI sorts by key yet.
+#[allow(dead_code)]
+pub fn par_radix_sort_by_key<T, K, F>(data: &mut [T], key: F)
+    where T: Copy + Sync + Send,
+          K: Radix + Copy + Sync + Send,
+          F: Fn(&T) -> K + Sync
+{
+    let mut keys = data.par_iter().map(&key).collect::<Vec<K>>();
+    par_radix_sort(keys.as_mut_slice(), data);
+}
+
 #[cfg(test)]
 mod test {
     use test;
@@ -437,7 +540,7 @@ mod test {
         bench.iter(|| {
             super::radix_step(&mut *data, data_swap.as_mut_slice(),
                               carry.as_mut_slice(), carry_swap.as_mut_slice(),
-                              0,
+                              20,
                               &mut [0; 16], &mut [0; 16]);
         })
     }
@@ -458,6 +561,235 @@ mod test {
         })
     }
 
+    fn matches_integer_sort<T>(data: Vec<T>) -> bool
+        where T: super::Radix + Ord + Copy + Sync + Send + Default + ::std::fmt::Debug {
+        let mut sorted = data.clone();
+        sorted.sort();
+        let mut radix_sorted = data.clone();
+        let mut carry = vec![(); data.len()];
+        super::par_radix_sort(radix_sorted.as_mut_slice(), carry.as_mut_slice());
+        radix_sorted == sorted
+    }
+
+    quickcheck! {
+        fn matches_integer_sort_quickcheck(a: Vec<u8>, b: Vec<u16>, c: Vec<u32>, d: Vec<u64>, e: Vec<usize>) -> bool {
+            matches_integer_sort(a) && matches_integer_sort(b) && matches_integer_sort(c) && matches_integer_sort(d)
+                && matches_integer_sort(e)
+        }
+
+        fn matches_signed_sort_quickcheck(a: Vec<i8>, b: Vec<i16>, c: Vec<i32>, d: Vec<i64>, e: Vec<isize>) -> bool {
+            matches_integer_sort(a) && matches_integer_sort(b) && matches_integer_sort(c) && matches_integer_sort(d)
+                && matches_integer_sort(e)
+        }
+    }
+
+    // Whether par_radix_sort sorts keys like the (stable) standard sort does, values and all.
+    fn matches_stable_sort<K>(keys: Vec<K>) -> bool
+        where K: super::Radix + Ord + Copy + Sync + Send + Default + ::std::fmt::Debug {
+        let mut pairs = keys.iter().cloned().zip(0..keys.len()).collect::<Vec<(K, usize)>>();
+        pairs.sort_by_key(|&(key, _)| key);
+        let mut radix_keys = keys.clone();
+        let mut values = (0..keys.len()).collect::<Vec<usize>>();
+        super::par_radix_sort(radix_keys.as_mut_slice(), values.as_mut_slice());
+        let mut radix_pairs = keys.iter().cloned().zip(0..keys.len()).collect::<Vec<(K, usize)>>();
+        super::par_radix_sort_pairs(radix_pairs.as_mut_slice());
+        radix_keys.into_iter().zip(values).collect::<Vec<(K, usize)>>() == pairs && radix_pairs == pairs
+    }
+
+    quickcheck! {
+        fn stable_sort_quickcheck(a: Vec<u8>, b: Vec<i16>, c: Vec<u32>, d: Vec<i64>) -> bool {
+            matches_stable_sort(a) && matches_stable_sort(b) && matches_stable_sort(c) && matches_stable_sort(d)
+        }
+
+        fn stable_triplet_sort_quickcheck(data: Vec<u8>) -> bool {
+            // Only 2 values per byte, so there are plenty of equal triplets.
+            matches_stable_sort(triplet_slice(data.iter().map(|byte| byte % 2).collect()).into_vec())
+        }
+    }
+
+    #[test]
+    fn stable_sort_test() {
+        let keys = random_slice_from_alphabet(BENCH_SIZE, 16);
+        assert!(matches_stable_sort(keys.into_vec()));
+        let keys = random_slice::<u16>(BENCH_SIZE).iter().map(|key| key % 100).collect::<Vec<u16>>();
+        assert!(matches_stable_sort(keys));
+    }
+
+    #[test]
+    #[should_panic]
+    fn carry_len_test() {
+        super::par_radix_sort(&mut [3u32, 1, 2][..], &mut [0usize; 2][..]);
+    }
+
+    const DIGIT_WIDTHS: &[super::DigitWidth] = &[super::DigitWidth::Auto, super::DigitWidth::Bits4, super::DigitWidth::Bits8,
+                                                 super::DigitWidth::Bits11, super::DigitWidth::Bits16];
+
+    fn matches_sort_with_digit_width<T>(data: &[T], digit_width: super::DigitWidth) -> bool
+        where T: super::Radix + Ord + Copy + Sync + Send {
+        let mut pairs = data.iter().cloned().zip(0..data.len()).collect::<Vec<(T, usize)>>();
+        let mut radix_pairs = pairs.clone();
+        pairs.sort_by_key(|&(key, _)| key);
+        let (mut keys, mut values): (Vec<T>, Vec<usize>) = radix_pairs.drain(..).unzip();
+        super::par_radix_sort_with_digit_width(keys.as_mut_slice(), values.as_mut_slice(), digit_width);
+        keys.into_iter().zip(values).collect::<Vec<(T, usize)>>() == pairs
+    }
+
+    #[test]
+    fn digit_width_test() {
+        let wide = random_slice::<u64>(BENCH_SIZE);
+        let narrow = random_slice::<i32>(BENCH_SIZE).iter().map(|key| key % 5000).collect::<Vec<i32>>();
+        let triplets = random_triplet_slice(BENCH_SIZE);
+        for &digit_width in DIGIT_WIDTHS.iter() {
+            assert!(matches_sort_with_digit_width(wide.as_ref(), digit_width), "{:?}", digit_width);
+            assert!(matches_sort_with_digit_width(narrow.as_ref(), digit_width), "{:?}", digit_width);
+            assert!(matches_sort_with_digit_width(triplets.as_ref(), digit_width), "{:?}", digit_width);
+            assert!(matches_sort_with_digit_width(&wide[..100], digit_width), "{:?}", digit_width);
+        }
+        assert_eq!(super::DigitWidth::Bits16.bits_for(1 << 20), 16);
+        assert_eq!(super::DigitWidth::Bits16.bits_for(1000), super::DigitWidth::Auto.bits_for(1000));
+        assert_eq!(super::DigitWidth::Bits4.bits_for(1 << 20), 4);
+        assert_eq!(super::DigitWidth::from_bits(11), Some(super::DigitWidth::Bits11));
+        assert!(super::DigitWidth::from_bits(12).is_none());
+    }
+
+    #[test]
+    fn indices_digit_width_test() {
+        let dat = random_slice_with_zeroes(BENCH_SIZE);
+        let dat_triplet = to_suffix_triplet_slice(dat.as_ref());
+        let mut sorted_order = (0..BENCH_SIZE - 2).collect::<Vec<usize>>();
+        sorted_order.sort_by_key(|&i| dat_triplet[i]);
+        for &digit_width in DIGIT_WIDTHS.iter() {
+            let mut indices = (0..BENCH_SIZE - 2).collect::<Vec<usize>>();
+            super::par_radix_indices_sort(dat.as_ref(), indices.as_mut_slice(), 24, digit_width, &|data, index| {
+                (data[index] as u64) << 16 | (data[index + 1] as u64) << 8 | data[index + 2] as u64
+            });
+            assert_eq!(indices, sorted_order, "{:?}", digit_width);
+        }
+    }
+
+    #[test]
+    fn integer_sort_test() {
+        assert!(matches_integer_sort(random_slice::<u64>(BENCH_SIZE).into_vec()));
+        assert!(matches_integer_sort(random_slice::<i32>(BENCH_SIZE).into_vec()));
+        assert!(matches_integer_sort(vec![i64::MIN, -1, 0, 1, i64::MAX, -1, i64::MIN]));
+    }
+
+    #[test]
+    fn sort_by_key_test() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Record {
+            id: usize,
+            score: i32,
+            grade: u8,
+            delta: i8
+        }
+        let mut records = random_slice::<i32>(BENCH_SIZE).iter().enumerate()
+            .map(|(id, &score)| Record { id, score: score % 1000, grade: score as u8, delta: (score >> 8) as i8 })
+            .collect::<Vec<Record>>();
+        let mut sorted = records.clone();
+        sorted.sort_by_key(|record| record.score);
+        super::par_radix_sort_by_key(records.as_mut_slice(), |record| record.score);
+        assert_eq!(records, sorted);
+
+        // One byte keys take a single pass, an odd number of them, which is where the carry used to go wrong.
+        sorted.sort_by_key(|record| record.grade);
+        super::par_radix_sort_by_key(records.as_mut_slice(), |record| record.grade);
+        assert_eq!(records, sorted);
+        sorted.sort_by_key(|record| record.delta);
+        super::par_radix_sort_by_key(records.as_mut_slice(), |record| record.delta);
+        assert_eq!(records, sorted);
+        assert!(records.windows(2).any(|w| w[0].delta < 0 && w[1].delta >= 0));
+    }
+
+    quickcheck! {
+        fn matches_strings_sort_quickcheck(data: Vec<Vec<u8>>) -> bool {
+            let mut sorted = data.clone();
+            sorted.sort();
+            let mut radix_sorted = data;
+            super::par_radix_strings_sort(radix_sorted.as_mut_slice());
+            radix_sorted == sorted
+        }
+    }
+
+    #[test]
+    fn strings_sort_test() {
+        // Few distinct bytes and lots of shared prefixes, so every kind of bucket comes up.
+        let mut strings = (0..BENCH_SIZE / 16).map(|len| random_slice_from_alphabet(len % 40, 2).into_vec()).collect::<Vec<Vec<u8>>>();
+        strings.extend(strings.clone());
+        let mut sorted = strings.clone();
+        sorted.sort();
+        super::par_radix_strings_sort(strings.as_mut_slice());
+        assert_eq!(strings, sorted);
+    }
+
+    #[test]
+    fn long_run_suffixes_sort_test() {
+        // Every step only splits off the shortest suffix, which used to take a stack frame per byte.
+        let mut data = vec![b'a'; 10000];
+        let mut suffixes = (0..data.len()).collect::<Vec<usize>>();
+        super::par_radix_strings_indices_sort(suffixes.as_mut_slice(), &|index| &data[index..]);
+        assert!(suffixes.iter().rev().cloned().eq(0..data.len()));
+
+        data.push(b'b');
+        let mut suffixes = (0..data.len()).rev().collect::<Vec<usize>>();
+        super::par_radix_strings_indices_sort(suffixes.as_mut_slice(), &|index| &data[index..]);
+        assert!(suffixes.iter().cloned().eq(0..data.len()));
+    }
+
+    #[test]
+    fn suffixes_sort_test() {
+        for data in [random_slice_from_alphabet(DEFAULT_TEST_SIZE, 4), random_repetitive_slice(DEFAULT_TEST_SIZE)] {
+            let mut suffixes = (0..data.len()).collect::<Vec<usize>>();
+            super::par_radix_strings_indices_sort(suffixes.as_mut_slice(), &|index| &data[index..]);
+            assert_eq!(suffixes.as_slice(), ::suffix_array(data.as_ref()).unwrap().as_ref());
+        }
+    }
+
+    #[bench]
+    fn radix_strings_bench(bench: &mut test::Bencher) {
+        let data = random_slice_from_alphabet(BENCH_SIZE, 4);
+        bench.iter(|| {
+            let mut prefixes = (0..data.len()).collect::<Vec<usize>>();
+            super::par_radix_strings_indices_sort(prefixes.as_mut_slice(), &|index| &data[index..(index + 16).min(data.len())]);
+        })
+    }
+
+    #[bench]
+    fn par_sort_strings_bench(bench: &mut test::Bencher) {
+        let data = random_slice_from_alphabet(BENCH_SIZE, 4);
+        bench.iter(|| {
+            let mut prefixes = (0..data.len()).map(|index| &data[index..(index + 16).min(data.len())]).collect::<Vec<&[u8]>>();
+            prefixes.par_sort();
+        })
+    }
+
+    #[bench]
+    fn radix_u64_bench(bench: &mut test::Bencher) {
+        bench.iter(|| {
+            let mut arr = random_slice::<u64>(BENCH_SIZE);
+            let mut carry = vec![(); arr.len()];
+            super::par_radix_sort(&mut arr, carry.as_mut_slice());
+        })
+    }
+
+    #[bench]
+    fn radix_u64_8_bit_bench(bench: &mut test::Bencher) {
+        bench.iter(|| {
+            let mut arr = random_slice::<u64>(BENCH_SIZE);
+            let mut carry = vec![(); arr.len()];
+            super::par_radix_sort_with_digit_width(&mut arr, carry.as_mut_slice(), super::DigitWidth::Bits8);
+        })
+    }
+
+    #[bench]
+    fn radix_u64_16_bit_bench(bench: &mut test::Bencher) {
+        bench.iter(|| {
+            let mut arr = random_slice::<u64>(BENCH_SIZE);
+            let mut carry = vec![(); arr.len()];
+            super::par_radix_sort_with_digit_width(&mut arr, carry.as_mut_slice(), super::DigitWidth::Bits16);
+        })
+    }
+
     #[test]
     fn simple_sort() {
         let mut x = [[1, 3, 55], [249, 24, 4], [1, 2, 127], [1, 2, 126]];
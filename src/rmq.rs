@@ -0,0 +1,230 @@
+use std;
+use rayon::prelude::*;
+use ansv;
+
+// Range minimum queries: query(l, r) gives the minimum of base[l..=r] along with the position of
+// its leftmost occurrence.
+//
+// - SparseTable:
+//     table[k][i] is the position of the minimum of base[i..i + 2^k]. Any range is covered by two
+// (possibly overlapping) power of two windows, one starting at l and one ending at r, so queries are
+// O(1). Each row only depends on the row below it, so the rows are built one at a time with every
+// row in parallel. The catch is O(n log n) space.
+//
+// - MinTree:
+//     The min search tree from ansv, with the input as its bottom layer. O(n) extra space (about
+// one more copy of the input) and O(log n) queries. This is the one to use on anything the size of
+// a suffix array.
+
+pub trait RangeMin<T> {
+    fn query(&self, l: usize, r: usize) -> (T, usize);
+}
+
+#[allow(dead_code)]
+#[inline(always)]
+fn floor_log2(n: usize) -> usize {
+    (std::mem::size_of::<usize>() * 8 - 1) - n.leading_zeros() as usize
+}
+
+// Nothing outside the tests builds a SparseTable yet, lpf_from_lcp needs the MinTree's O(n) space.
+#[allow(dead_code)]
+pub struct SparseTable<'a, T: 'a> {
+    base: &'a [T],
+    table: Box<[Box<[usize]>]>
+}
+
+impl<'a, T> SparseTable<'a, T> where T: Ord + Copy + Send + Sync {
+    #[allow(dead_code)]
+    pub fn new(base: &'a [T]) -> SparseTable<'a, T> {
+        let mut table = Vec::<Box<[usize]>>::new();
+        table.push((0..base.len()).collect::<Vec<usize>>().into_boxed_slice());
+
+        let mut width = 1;
+        while width * 2 <= base.len() {
+            let next_row = {
+                let row = table.last().unwrap();
+                (0..base.len() + 1 - width * 2).into_par_iter().map(|i| {
+                    let (l, r) = (row[i], row[i + width]);
+                    if base[r] < base[l] {
+                        r
+                    } else {
+                        l
+                    }
+                }).collect::<Vec<usize>>().into_boxed_slice()
+            };
+            table.push(next_row);
+            width *= 2;
+        }
+
+        SparseTable {
+            base,
+            table: table.into_boxed_slice()
+        }
+    }
+}
+
+impl<'a, T> RangeMin<T> for SparseTable<'a, T> where T: Ord + Copy {
+    #[inline]
+    fn query(&self, l: usize, r: usize) -> (T, usize) {
+        let k = floor_log2(r - l + 1);
+        let left_pos = self.table[k][l];
+        let right_pos = self.table[k][r + 1 - (1 << k)];
+        if self.base[right_pos] < self.base[left_pos] {
+            (self.base[right_pos], right_pos)
+        } else {
+            (self.base[left_pos], left_pos)
+        }
+    }
+}
+
+pub struct MinTree<'a, T: 'a> {
+    base: &'a [T],
+    tree: ansv::ArrayTree<T>
+}
+
+impl<'a, T> MinTree<'a, T> where T: Ord + Copy + Default + Send + Sync {
+    pub fn new(base: &'a [T]) -> MinTree<'a, T> {
+        MinTree {
+            base,
+            tree: ansv::construct_min_search_tree(base)
+        }
+    }
+}
+
+impl<'a, T> MinTree<'a, T> {
+    #[inline(always)]
+    fn val_at(&self, depth: usize, idx: usize) -> &T {
+        if depth == 0 {
+            &self.base[idx]
+        } else {
+            &self.tree.layers()[depth - 1][idx]
+        }
+    }
+}
+
+impl<'a, T> RangeMin<T> for MinTree<'a, T> where T: Ord + Copy {
+    // Walks up from both ends at once, taking a node whenever its sibling would stick out of the range.
+    // The nodes taken from the left end come in left to right order and the ones from the right end in
+    // right to left order, so we keep the first minimum from the left and the last one from the right.
+    // Whichever of those two nodes is leftmost holding the minimum is then walked back down, preferring
+    // the left child, to find where the minimum actually is.
+    fn query(&self, l: usize, r: usize) -> (T, usize) {
+        let mut best_left: Option<(usize, usize)> = None;
+        let mut best_right: Option<(usize, usize)> = None;
+
+        let mut lo = l;
+        let mut hi = r + 1;
+        let mut depth = 0;
+        while lo < hi {
+            if lo & 1 == 1 {
+                best_left = match best_left {
+                    Some((d, i)) if *self.val_at(d, i) <= *self.val_at(depth, lo) => best_left,
+                    _ => Some((depth, lo))
+                };
+                lo += 1;
+            }
+            if hi & 1 == 1 {
+                hi -= 1;
+                best_right = match best_right {
+                    Some((d, i)) if *self.val_at(d, i) < *self.val_at(depth, hi) => best_right,
+                    _ => Some((depth, hi))
+                };
+            }
+            lo >>= 1;
+            hi >>= 1;
+            depth += 1;
+        }
+
+        let (mut depth, mut idx) = match (best_left, best_right) {
+            (Some((ld, li)), Some((rd, ri))) => if *self.val_at(rd, ri) < *self.val_at(ld, li) {
+                (rd, ri)
+            } else {
+                (ld, li)
+            },
+            (Some(node), None) | (None, Some(node)) => node,
+            (None, None) => unreachable!()
+        };
+
+        let min = *self.val_at(depth, idx);
+        while depth > 0 {
+            depth -= 1;
+            idx <<= 1;
+            if *self.val_at(depth, idx) != min {
+                idx += 1;
+            }
+        }
+
+        (min, idx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils;
+    use rand;
+    use rand::Rng;
+    use test;
+    use super::RangeMin;
+
+    fn naive_query(data: &[u8], l: usize, r: usize) -> (u8, usize) {
+        let min = *data[l..r + 1].iter().min().unwrap();
+        (min, l + data[l..r + 1].iter().position(|&v| v == min).unwrap())
+    }
+
+    fn validate_rmq<R: RangeMin<u8>>(data: &[u8], rmq: &R) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..utils::DEFAULT_TEST_SAMPLE_SIZE {
+            let l = rng.gen_range(0, data.len());
+            let r = rng.gen_range(l, (l + 512).min(data.len()));
+            assert_eq!(rmq.query(l, r), naive_query(data, l, r));
+        }
+        for (i, &el) in data.iter().enumerate() {
+            assert_eq!(rmq.query(i, i), (el, i));
+        }
+        assert_eq!(rmq.query(0, data.len() - 1), naive_query(data, 0, data.len() - 1));
+    }
+
+    #[test]
+    fn single_element_test() {
+        let data = [7u8];
+        assert_eq!(super::SparseTable::new(&data[..]).query(0, 0), (7, 0));
+        assert_eq!(super::MinTree::new(&data[..]).query(0, 0), (7, 0));
+    }
+
+    #[test]
+    fn sparse_table_test() {
+        // Small alphabet so there are lots of ties for the leftmost rule to get wrong.
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE + 3, 16);
+        validate_rmq(data.as_ref(), &super::SparseTable::new(data.as_ref()));
+    }
+
+    #[test]
+    fn min_tree_test() {
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE + 3, 16);
+        validate_rmq(data.as_ref(), &super::MinTree::new(data.as_ref()));
+    }
+
+    #[bench]
+    fn sparse_table_query_bench(bench: &mut test::Bencher) {
+        let data = utils::random_slice::<u8>(utils::BENCH_SIZE);
+        let rmq = super::SparseTable::new(data.as_ref());
+        let mut rng = rand::thread_rng();
+        bench.iter(|| {
+            let l = rng.gen_range(0, data.len());
+            let r = rng.gen_range(l, data.len());
+            rmq.query(l, r)
+        })
+    }
+
+    #[bench]
+    fn min_tree_query_bench(bench: &mut test::Bencher) {
+        let data = utils::random_slice::<u8>(utils::BENCH_SIZE);
+        let rmq = super::MinTree::new(data.as_ref());
+        let mut rng = rand::thread_rng();
+        bench.iter(|| {
+            let l = rng.gen_range(0, data.len());
+            let r = rng.gen_range(l, data.len());
+            rmq.query(l, r)
+        })
+    }
+}
@@ -0,0 +1,167 @@
+use std::cmp::Ordering;
+use rayon::prelude::*;
+
+// Explanation:
+// - Searching a suffix array:
+//     Every occurrence of a pattern is the start of a suffix which has the pattern as a prefix, and since the
+// suffix array is sorted those suffixes are all next to each other. So two binary searches, one for the first
+// suffix whose prefix is >= the pattern and one for the first whose prefix is > the pattern, give the range of
+// the suffix array holding every occurrence.
+//
+// - LCP acceleration:
+//     Plain binary search compares the pattern from its first character at every step, which is slow when the
+// pattern is long and matches a lot. But if the suffixes at both ends of the current range share h characters
+// with the pattern, then so does every suffix in between (they're sorted), so we can skip straight to
+// character min(lo_lcp, hi_lcp). This is the "mlr" heuristic from Manber and Myers.
+
+pub struct SuffixArraySearch<'a> {
+    data: &'a [u8],
+    suffix_array: &'a [usize]
+}
+
+impl<'a> SuffixArraySearch<'a> {
+    pub fn new(data: &'a [u8], suffix_array: &'a [usize]) -> SuffixArraySearch<'a> {
+        SuffixArraySearch {
+            data,
+            suffix_array
+        }
+    }
+
+    // How the suffix at pos compares to the pattern, looking at the first pattern.len() characters only,
+    // and how many characters they share. The first skip characters are assumed to match.
+    #[inline]
+    fn compare(&self, pattern: &[u8], pos: usize, skip: usize) -> (Ordering, usize) {
+        let suffix = &self.data[pos..];
+        let lcp = skip + pattern[skip..].iter().zip(suffix[skip.min(suffix.len())..].iter())
+            .take_while(|&(&p, &s)| p == s)
+            .count();
+        if lcp == pattern.len() {
+            (Ordering::Equal, lcp)
+        } else if lcp == suffix.len() || suffix[lcp] < pattern[lcp] {
+            (Ordering::Less, lcp)
+        } else {
+            (Ordering::Greater, lcp)
+        }
+    }
+
+    // First position in the suffix array whose suffix doesn't compare below the pattern (or above it, if
+    // strict is set).
+    fn bound(&self, pattern: &[u8], strict: bool) -> usize {
+        let mut lo = 0;
+        let mut hi = self.suffix_array.len();
+        let mut lo_lcp = 0;
+        let mut hi_lcp = 0;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (ordering, lcp) = self.compare(pattern, self.suffix_array[mid], lo_lcp.min(hi_lcp));
+            let go_right = match ordering {
+                Ordering::Less => true,
+                Ordering::Equal => strict,
+                Ordering::Greater => false
+            };
+            if go_right {
+                lo = mid + 1;
+                lo_lcp = lcp;
+            } else {
+                hi = mid;
+                hi_lcp = lcp;
+            }
+        }
+        lo
+    }
+
+    // The range of the suffix array holding every occurrence of the pattern.
+    pub fn range(&self, pattern: &[u8]) -> (usize, usize) {
+        let start = self.bound(pattern, false);
+        let end = self.bound(pattern, true);
+        (start, end)
+    }
+
+    pub fn count(&self, pattern: &[u8]) -> usize {
+        let (start, end) = self.range(pattern);
+        end - start
+    }
+
+    // Some occurrence of the pattern, not necessarily the first one. The CLI always wants all of them.
+    #[allow(dead_code)]
+    pub fn find(&self, pattern: &[u8]) -> Option<usize> {
+        let (start, end) = self.range(pattern);
+        if start < end {
+            Some(self.suffix_array[start])
+        } else {
+            None
+        }
+    }
+
+    // Every occurrence of the pattern, in order.
+    pub fn locate(&self, pattern: &[u8]) -> Box<[usize]> {
+        let (start, end) = self.range(pattern);
+        let mut positions = self.suffix_array[start..end].to_vec();
+        positions.par_sort_unstable();
+        positions.into_boxed_slice()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils;
+    use saxx;
+    use serial_suffix::SuffixTable;
+    use rand;
+    use rand::Rng;
+    use test;
+
+    fn suffix_array(data: &[u8]) -> Box<[usize]> {
+        let esa = saxx::Esaxx::<i64>::esaxx(data).unwrap();
+        esa.sa.iter().map(|&v| v as usize).collect::<Vec<usize>>().into_boxed_slice()
+    }
+
+    #[test]
+    fn locate_fixed_test() {
+        let data = b"the quick brown fox was quick.";
+        let sa = suffix_array(data.as_ref());
+        let search = super::SuffixArraySearch::new(data.as_ref(), sa.as_ref());
+        assert_eq!(search.locate(b"quick").as_ref(), &[4, 24]);
+        assert_eq!(search.count(b"quick"), 2);
+        assert!(search.find(b"quick").is_some());
+        assert!(search.find(b"faux").is_none());
+        assert_eq!(search.count(b""), data.len());
+        assert_eq!(search.count(b"quick. and then some"), 0);
+    }
+
+    #[test]
+    fn locate_matches_suffix_table_test() {
+        let data = utils::random_slice_from_alphabet(utils::DEFAULT_TEST_SIZE, 4);
+        let sa = suffix_array(data.as_ref());
+        let search = super::SuffixArraySearch::new(data.as_ref(), sa.as_ref());
+        let table = SuffixTable::new(String::from_utf8_lossy(data.as_ref()));
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..utils::DEFAULT_TEST_SAMPLE_SIZE {
+            let len = rng.gen_range(1, 16);
+            let start = rng.gen_range(0, data.len() - len);
+            // Half the time a pattern we know is there, otherwise whatever comes out.
+            let pattern = if rng.gen() {
+                data[start..start + len].to_vec()
+            } else {
+                (0..len).map(|_| rng.gen_range(0, 4)).collect::<Vec<u8>>()
+            };
+            let mut expected = table.positions(String::from_utf8_lossy(pattern.as_ref()).as_ref()).iter()
+                .map(|&p| p as usize).collect::<Vec<usize>>();
+            expected.sort();
+            assert_eq!(search.locate(pattern.as_ref()).as_ref(), expected.as_slice());
+        }
+    }
+
+    #[bench]
+    fn count_bench(bencher: &mut test::Bencher) {
+        let data = utils::random_repetitive_slice(utils::BENCH_SIZE);
+        let sa = suffix_array(data.as_ref());
+        let search = super::SuffixArraySearch::new(data.as_ref(), sa.as_ref());
+        let mut rng = rand::thread_rng();
+        bencher.iter(|| {
+            let start = rng.gen_range(0, data.len() - 256);
+            search.count(&data[start..start + 256])
+        });
+    }
+}
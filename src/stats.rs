@@ -0,0 +1,149 @@
+use std::fmt::Write;
+
+// Explanation:
+// - Reports:
+//     A run's numbers as (name, value) pairs, kept in the order they were added so every run of the same
+// command comes out with the same columns. Names are fixed identifiers, so they never need quoting, but text
+// values can be anything (e.g. a file name), so they're escaped as JSON strings and quoted CSV cells. A value
+// we don't have (e.g. the compressed size when nothing was written) is null in JSON and an empty cell in CSV,
+// rather than a missing column.
+//
+// - Several reports:
+//     Commands that measure more than one thing (bench) print a report per row: one JSON object per line,
+// or a single CSV header followed by a line of values per report.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(u64),
+    Float(f64),
+    Text(String),
+    Missing
+}
+
+impl Value {
+    fn write_json(&self, out: &mut String) {
+        match *self {
+            Value::Text(ref text) => {
+                out.push('"');
+                for c in text.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+                        c => out.push(c)
+                    }
+                }
+                out.push('"');
+            }
+            _ => self.write_number(out, "null")
+        }
+    }
+
+    fn write_csv(&self, out: &mut String) {
+        match *self {
+            Value::Text(ref text) if text.contains([',', '"', '\n', '\r']) => {
+                write!(out, "\"{}\"", text.replace("\"", "\"\"")).unwrap()
+            }
+            Value::Text(ref text) => out.push_str(text),
+            _ => self.write_number(out, "")
+        }
+    }
+
+    fn write_number(&self, out: &mut String, missing: &str) {
+        match *self {
+            Value::Integer(value) => write!(out, "{}", value).unwrap(),
+            Value::Float(value) if value.is_finite() => write!(out, "{}", value).unwrap(),
+            _ => out.push_str(missing)
+        }
+    }
+}
+
+pub struct Report {
+    fields: Vec<(&'static str, Value)>
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report {
+            fields: Vec::new()
+        }
+    }
+
+    pub fn push(&mut self, name: &'static str, value: Value) {
+        self.fields.push((name, value));
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|&&(field, _)| field == name).map(|(_, value)| value)
+    }
+
+    // A single JSON object.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        for (idx, &(name, ref value)) in self.fields.iter().enumerate() {
+            if idx > 0 {
+                out.push_str(", ");
+            }
+            write!(out, "\"{}\": ", name).unwrap();
+            value.write_json(&mut out);
+        }
+        out.push('}');
+        out
+    }
+
+    pub fn csv_header(&self) -> String {
+        self.fields.iter().map(|&(name, _)| name).collect::<Vec<&str>>().join(",")
+    }
+
+    pub fn csv_values(&self) -> String {
+        let mut out = String::new();
+        for (idx, (_, value)) in self.fields.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            value.write_csv(&mut out);
+        }
+        out
+    }
+
+    // A header line and a line of values.
+    pub fn to_csv(&self) -> String {
+        format!("{}\n{}", self.csv_header(), self.csv_values())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Report, Value};
+
+    fn report() -> Report {
+        let mut report = Report::new();
+        report.push("input_bytes", Value::Integer(1024));
+        report.push("lpf_seconds", Value::Float(0.25));
+        report.push("compressed_bytes", Value::Missing);
+        report.push("ratio", Value::Float(1f64 / 0f64));
+        report
+    }
+
+    #[test]
+    fn json_test() {
+        assert_eq!(report().to_json(), "{\"input_bytes\": 1024, \"lpf_seconds\": 0.25, \"compressed_bytes\": null, \"ratio\": null}");
+        assert_eq!(Report::new().to_json(), "{}");
+    }
+
+    #[test]
+    fn csv_test() {
+        assert_eq!(report().to_csv(), "input_bytes,lpf_seconds,compressed_bytes,ratio\n1024,0.25,,");
+    }
+
+    #[test]
+    fn text_test() {
+        let mut report = Report::new();
+        report.push("corpus", Value::Text("dna".to_owned()));
+        report.push("file", Value::Text("a \"b\", c\n".to_owned()));
+        assert_eq!(report.to_json(), "{\"corpus\": \"dna\", \"file\": \"a \\\"b\\\", c\\n\"}");
+        assert_eq!(report.csv_values(), "dna,\"a \"\"b\"\", c\n\"");
+    }
+}
@@ -2,6 +2,8 @@ use radix;
 use rayon::prelude::*;
 use rayon;
 use utils;
+use utils::Symbol;
+use error::Error;
 
 // Data must end with two 0 entries which are not used
 //fn suffix_array(data: & [u8], suffix_array: &mut [usize]) {
@@ -42,7 +44,7 @@ use utils;
 //}
 
 
-fn adjust_bounds_to_as(data: &[u8], bounds: &mut [usize]) {
+fn adjust_bounds_to_as<T: Ord + Sync>(data: &[T], bounds: &mut [usize]) {
     bounds.par_iter_mut().for_each(|bound| {
         if *bound == data.len() {
             return;
@@ -53,15 +55,29 @@ fn adjust_bounds_to_as(data: &[u8], bounds: &mut [usize]) {
     });
 }
 
+// Size of the byte alphabet. The buckets below are indexed by symbol, so for other alphabets they're sized
+// by alphabet_size instead (the largest symbol + 1). Bucket B has one entry per pair of symbols though, and
+// there's a copy of it per chunk, another as the reduce identity and one per thread in init_b_star, so it's
+// alphabet_size² usizes several times over. suffix_array renumbers wider alphabets to just the symbols that
+// occur first, which only helps when few of them do (60k distinct token ids would still be ~29 GB a copy),
+// so past MAX_ALPHABET_SIZE of them it turns the input down.
 const A_SIZE: usize = 256;
-const B_DIM: usize = A_SIZE;
+// Keeps every copy of bucket B at 8 MB.
+const MAX_ALPHABET_SIZE: usize = 1024;
+
+fn alphabet_size<T: Symbol>(data: &[T]) -> usize {
+    data.par_iter().map(|&c| c.index() + 1).max().unwrap_or(0)
+}
+
+// Bucket B, one row of alphabet_size counts per symbol.
+type BucketB = Box<[Box<[usize]>]>;
 
 enum CharType {A, B, BSTAR}
 //const B_SIZE: usize = A_SIZE * A_SIZE;
 
 // This is explained in Nong 2009 and Labeit 2017.
 #[inline]
-fn cur_type(next_type:CharType, next_val: u8, cur_val: u8) -> CharType {
+fn cur_type<T: Ord>(next_type:CharType, next_val: T, cur_val: T) -> CharType {
     if cur_val > next_val {
         return CharType::A
     } else if cur_val < next_val {
@@ -88,16 +104,17 @@ fn cur_type(next_type:CharType, next_val: u8, cur_val: u8) -> CharType {
 // After running this bucket_b in the bstar positions contains a prefix sum of the b_star counts
 // Bucket_a seemingly contains the ranges for suffixes starting with a given char in the final sorted array.
 // Bucket_b seems worthless.
-fn calculate_bucket_offsets(bucket_a: &mut [usize], bucket_b: &mut [[usize; B_DIM]]) {
+fn calculate_bucket_offsets(bucket_a: &mut [usize], bucket_b: &mut [Box<[usize]>]) {
     let mut a_b_prefix_sum = 0usize;
     let mut bstar_prefix_sum = 0usize;
+    let alphabet_size = bucket_a.len();
 
-    (0..A_SIZE).for_each(|char0| {
+    (0..alphabet_size).for_each(|char0| {
         let cur_a_bucket_val = bucket_a[char0];
         bucket_a[char0] = a_b_prefix_sum + bstar_prefix_sum; // As they say in the original... "Start point"
         a_b_prefix_sum += cur_a_bucket_val + bucket_b[char0][char0];
 
-        (char0 + 1..A_SIZE).for_each(|char1| {
+        (char0 + 1..alphabet_size).for_each(|char1| {
             // Prefix sum in lexicographic order over BSTAR buckets for char0.
             bstar_prefix_sum += bucket_b[char0][char1];
             bucket_b[char0][char1] = bstar_prefix_sum; // As they say in the original code.. "End point"
@@ -110,7 +127,7 @@ fn calculate_bucket_offsets(bucket_a: &mut [usize], bucket_b: &mut [[usize; B_DI
 
 // Counts occurences of A, B, and BSTAR bytes and byte pairs
 // finds indexes of all BSTAR bytes
-fn gen_a_b_offsets_and_b_star_indices<'a>(data: &'a [u8]) -> (Box<[usize]>, Box<[[usize; B_DIM]]>, Box<[usize]>) {
+fn gen_a_b_offsets_and_b_star_indices<T: Symbol>(data: &[T], alphabet_size: usize) -> (Box<[usize]>, BucketB, Box<[usize]>) {
     let mut bounds = utils::bounds_for_num_chunks(data.len(), rayon::current_num_threads());
     adjust_bounds_to_as(data, bounds.as_mut());
 
@@ -118,19 +135,19 @@ fn gen_a_b_offsets_and_b_star_indices<'a>(data: &'a [u8]) -> (Box<[usize]>, Box<
     let mut nums_b_star_indices = vec![0usize; bounds.len()].into_boxed_slice();
 
     let (mut a_buckets, mut b_buckets) = sub_slices.par_iter().zip(nums_b_star_indices.par_iter_mut())
-        .map(|(&slice, num_b_star_indices): (&&[u8], &mut usize)| {
+        .map(|(&slice, num_b_star_indices): (&&[T], &mut usize)| {
 
-        let mut a_buckets = [0usize; A_SIZE];
-        let mut b_buckets = vec![[0usize; B_DIM]; B_DIM];
+        let mut a_buckets = vec![0usize; alphabet_size].into_boxed_slice();
+        let mut b_buckets = vec![vec![0usize; alphabet_size].into_boxed_slice(); alphabet_size];
 
-        slice.iter().rev().enumerate().fold((CharType::B, 0u8), |(next_type, next_val), (idx, &cur_val): (usize, &u8)| {
+        slice.iter().rev().enumerate().fold((CharType::B, T::default()), |(next_type, next_val), (_, &cur_val): (usize, &T)| {
             let cur_type = cur_type(next_type, next_val, cur_val);
             match cur_type {
-                CharType::A => a_buckets[cur_val as usize] += 1,
-                CharType::B => b_buckets[next_val as usize][cur_val as usize] += 1,
+                CharType::A => a_buckets[cur_val.index()] += 1,
+                CharType::B => b_buckets[next_val.index()][cur_val.index()] += 1,
                 CharType::BSTAR => {
                     *num_b_star_indices += 1;
-                    b_buckets[cur_val as usize][next_val as usize] += 1;
+                    b_buckets[cur_val.index()][next_val.index()] += 1;
                 }
             }
             return (cur_type, cur_val)
@@ -138,7 +155,7 @@ fn gen_a_b_offsets_and_b_star_indices<'a>(data: &'a [u8]) -> (Box<[usize]>, Box<
 
         (a_buckets, b_buckets)
 
-    }).reduce(|| ([0usize; A_SIZE], vec![[0usize; B_DIM]; B_DIM]),
+    }).reduce(|| (vec![0usize; alphabet_size].into_boxed_slice(), vec![vec![0usize; alphabet_size].into_boxed_slice(); alphabet_size]),
               |(mut a_buckets_left, mut b_buckets_left), (a_buckets_right, b_buckets_right)| {
 
                   a_buckets_left.iter_mut().zip(a_buckets_right.iter()).for_each(|(l, &r): (&mut usize, &usize)| *l += r);
@@ -168,7 +185,7 @@ fn gen_a_b_offsets_and_b_star_indices<'a>(data: &'a [u8]) -> (Box<[usize]>, Box<
                         let mut bsi_iterator = b_star_indices.iter_mut().rev();
 
                         // Iterate through the array the same way as the first time, still tracking state as we go
-                        slice.iter().rev().enumerate().fold((CharType::B, 0u8),
+                        slice.iter().rev().enumerate().fold((CharType::B, T::default()),
                                                             |(next_type, next_val), (data_idx, &cur_val)| {
                                                                 let cur_type = cur_type(next_type, next_val, cur_val);
                                                                 match cur_type {
@@ -184,20 +201,23 @@ fn gen_a_b_offsets_and_b_star_indices<'a>(data: &'a [u8]) -> (Box<[usize]>, Box<
         });
 
 
-    (Box::new(a_buckets), b_buckets.into_boxed_slice(), b_star_indices)
+    (a_buckets, b_buckets.into_boxed_slice(), b_star_indices)
 }
 
 
 // God only knows the purpose of this fucking trasher
-fn init_b_star(data: &[u8], b_offsets: &mut [[usize; B_DIM]], bstar_indices: &[usize]) {
-    let mut scratchpads = vec![vec![[0usize; B_DIM]; B_DIM].into_boxed_slice(); rayon::current_num_threads()].into_boxed_slice();
+// suffix_array doesn't get as far as calling it yet.
+#[allow(dead_code)]
+fn init_b_star<T: Symbol>(data: &[T], b_offsets: &mut [Box<[usize]>], bstar_indices: &[usize]) {
+    let alphabet_size = b_offsets.len();
+    let mut scratchpads = vec![vec![vec![0usize; alphabet_size].into_boxed_slice(); alphabet_size].into_boxed_slice(); rayon::current_num_threads()].into_boxed_slice();
     {
         utils::n_split_slice(bstar_indices, scratchpads.len()).par_iter().zip(scratchpads.par_iter_mut())
-            .for_each( |(&sub_bstar_indices, scratchpad): (&&[usize], &mut Box<[[usize;B_DIM]]>)| {
+            .for_each( |(&sub_bstar_indices, scratchpad): (&&[usize], &mut BucketB)| {
                 sub_bstar_indices.iter().for_each(|&idx| {
                     let char0 = data[idx];
                     let char1 = data[idx+1];
-                    scratchpad[char0 as usize][char1 as usize] += 1;
+                    scratchpad[char0.index()][char1.index()] += 1;
                 })
             });
     }
@@ -223,10 +243,24 @@ fn init_b_star(data: &[u8], b_offsets: &mut [[usize; B_DIM]], bstar_indices: &[u
 
 }
 
-pub fn suffix_array(data: & [u8], suffix_array: &mut [usize]) {
-    let (a_buckets, b_buckets, b_star_indices) = gen_a_b_offsets_and_b_star_indices(data);
-    println!("{:?}", a_buckets);
-
+// main still builds its suffix arrays with saxx, this one isn't finished yet.
+#[allow(dead_code)]
+pub fn suffix_array<T: Symbol>(data: & [T], _suffix_array: &mut [usize]) -> Result<(), Error> {
+    // Renumbering keeps the order, so the suffix array comes out the same.
+    let dense;
+    let data = if alphabet_size(data) > A_SIZE {
+        dense = utils::dense_symbols(data);
+        dense.as_ref()
+    } else {
+        data
+    };
+    let alphabet_size = alphabet_size(data);
+    if alphabet_size > MAX_ALPHABET_SIZE {
+        return Err(Error::Format(format!("Input has {} distinct symbols, but suffix sorting only takes up to {}.",
+                                         alphabet_size, MAX_ALPHABET_SIZE)));
+    }
+    let (_a_buckets, _b_buckets, _b_star_indices) = gen_a_b_offsets_and_b_star_indices(data, alphabet_size);
+    Ok(())
 }
 
 
@@ -239,13 +273,13 @@ mod test {
 
     #[test]
     fn init_b_star_test() {
-        let data = random_slice(DEFAULT_TEST_SIZE);
-        let (mut a_offsets, mut b_offsets, mut b_star_indices) = super::gen_a_b_offsets_and_b_star_indices(data.as_ref());
+        let data = random_slice::<u8>(DEFAULT_TEST_SIZE);
+        let (_, mut b_offsets, b_star_indices) = super::gen_a_b_offsets_and_b_star_indices(data.as_ref(), super::A_SIZE);
         super::init_b_star(data.as_ref(), b_offsets.as_mut(), b_star_indices.as_ref());
     }
     #[test]
     fn a_bounds_test() {
-        let data = random_slice(DEFAULT_TEST_SIZE);
+        let data = random_slice::<u8>(DEFAULT_TEST_SIZE);
         let mut bounds = bounds_for_num_chunks(data.len(), rayon::current_num_threads());
         super::adjust_bounds_to_as(data.as_ref(), bounds.as_mut());
         bounds.iter().for_each(|&bound| {
@@ -257,14 +291,14 @@ mod test {
 
     #[bench]
     fn a_b_offsets_and_b_star_indices_bench(bench: &mut test::Bencher) {
-        let data = random_slice(BENCH_SIZE);
-        bench.iter(|| super::gen_a_b_offsets_and_b_star_indices(data.as_ref()));
+        let data = random_slice::<u8>(BENCH_SIZE);
+        bench.iter(|| super::gen_a_b_offsets_and_b_star_indices(data.as_ref(), super::A_SIZE));
     }
 
     #[test]
     fn a_b_counts_and_b_star_indices_test() {
-        let data = random_slice(DEFAULT_TEST_SIZE);
-        let (a_offsets, b_offsets, b_star_indices) = super::gen_a_b_offsets_and_b_star_indices(data.as_ref());
+        let data = random_slice::<u8>(DEFAULT_TEST_SIZE);
+        let (a_offsets, b_offsets, b_star_indices) = super::gen_a_b_offsets_and_b_star_indices(data.as_ref(), super::A_SIZE);
 
         // Check things are sorted and so on as we expect
         let mut histogram_prefix_sum = data.iter().fold([0usize; super::A_SIZE], |mut hist, byte| {
@@ -302,11 +336,38 @@ mod test {
         sorted.sort();
         assert_eq!(b_star_indices, sorted);
     }
+    #[test]
+    fn a_b_offsets_wide_alphabet_test() {
+        let data = random_slice::<u16>(DEFAULT_TEST_SIZE).iter().map(|&c| c % 300).collect::<Vec<u16>>();
+        let alphabet_size = super::alphabet_size(data.as_slice());
+        let (a_offsets, _, b_star_indices) = super::gen_a_b_offsets_and_b_star_indices(data.as_slice(), alphabet_size);
+
+        let mut histogram_prefix_sum = data.iter().fold(vec![0usize; alphabet_size], |mut hist, &c| {
+            hist[c as usize] += 1;
+            hist
+        });
+        histogram_prefix_sum.iter_mut().fold(0, |sum, count| {
+            let new_sum = sum + *count;
+            *count = sum;
+            new_sum
+        });
+        assert_eq!(histogram_prefix_sum.as_slice(), a_offsets.as_ref());
+
+        b_star_indices.par_iter().for_each(|&idx| {
+            assert!(data[idx] < data[idx + 1]);
+        });
+    }
+
     #[test]
     fn suffix_array_test() {
         let x = random_slice_with_zeroes(BENCH_SIZE);
         let mut suffix_array = vec![0; BENCH_SIZE];
-        super::suffix_array(x.as_ref(), suffix_array.as_mut());
+        super::suffix_array(x.as_ref(), suffix_array.as_mut()).unwrap();
 
+        // Sparse token ids are fine, as long as there aren't too many different ones.
+        let sparse = (0..DEFAULT_TEST_SIZE as u32).map(|i| (i % 500) * 7919 + (1 << 20)).collect::<Vec<u32>>();
+        assert!(super::suffix_array(sparse.as_slice(), suffix_array.as_mut()).is_ok());
+        let wide = (0..DEFAULT_TEST_SIZE as u32).map(|i| i % (super::MAX_ALPHABET_SIZE as u32 + 1)).collect::<Vec<u32>>();
+        assert_eq!(super::suffix_array(wide.as_slice(), suffix_array.as_mut()).unwrap_err().exit_code(), 4);
     }
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use std;
 use rayon;
+use rayon::prelude::*;
 use std::heap::Alloc;
 //use core::array::FixedSizeArray;
 
@@ -55,6 +56,29 @@ pub fn random_slice<T: rand::Rand>(len:usize) -> Box<[T]> {
     rng.gen_iter().take(len).collect::<Vec<T>>().into_boxed_slice()
 }
 
+#[cfg(test)]
+pub fn random_slice_from_alphabet(len: usize, alphabet_size: u8) -> Box<[u8]> {
+    let mut rng = rand::thread_rng();
+    (0..len).map(|_| rng.gen_range(0, alphabet_size)).collect::<Vec<u8>>().into_boxed_slice()
+}
+
+// A random block repeated over and over, with the occasional byte changed so the matches
+// are long but not the entire rest of the string.
+#[cfg(test)]
+pub fn random_repetitive_slice(len: usize) -> Box<[u8]> {
+    const BLOCK_SIZE: usize = 1024;
+    const MUTATION_RATE: usize = 4096;
+    let mut rng = rand::thread_rng();
+    let block = random_slice::<u8>(BLOCK_SIZE);
+    (0..len).map(|i| {
+        if rng.gen_range(0, MUTATION_RATE) == 0 {
+            rng.gen()
+        } else {
+            block[i % BLOCK_SIZE]
+        }
+    }).collect::<Vec<u8>>().into_boxed_slice()
+}
+
 #[cfg(test)]
 pub fn random_slice_with_zeroes(len: usize) -> Box<[u8]> {
     let mut res = random_slice(len);
@@ -155,6 +179,93 @@ pub fn rayon_chunk_size(slice_len: usize) -> usize {
     slice_len/(3*rayon::current_num_threads())
 }
 
+// Symbols we can build suffix structures over. index() is where the symbol goes in a table with one
+// entry per symbol, which for unsigned integers is just the value, and from_index() goes back.
+pub trait Symbol: Ord + Copy + Default + Send + Sync {
+    fn index(self) -> usize;
+    fn from_index(index: usize) -> Self;
+}
+
+macro_rules! impl_symbol {
+    ($($t:ty),*) => {
+        $(impl Symbol for $t {
+            #[inline(always)]
+            fn index(self) -> usize {
+                self as usize
+            }
+            #[inline(always)]
+            fn from_index(index: usize) -> $t {
+                index as $t
+            }
+        })*
+    }
+}
+
+impl_symbol!(u8, u16, u32);
+
+// Renumbers the symbols to their rank among the distinct symbols in data, keeping their order. Anything
+// with a table per symbol sizes it by the largest symbol, which for raw token ids can be far bigger than
+// the number of symbols actually used (a single 0xFFFFFFF0 would ask for billions of entries). There can't
+// be more distinct symbols than values of T, so the ranks always fit.
+pub fn dense_symbols<T: Symbol>(data: &[T]) -> Box<[T]> {
+    let mut alphabet = data.to_vec();
+    alphabet.par_sort_unstable();
+    alphabet.dedup();
+    data.par_iter().map(|c| T::from_index(alphabet.binary_search(c).unwrap())).collect::<Vec<T>>().into_boxed_slice()
+}
+
+pub fn float_secs(d: std::time::Duration) -> f64 {
+    (d.as_secs() as f64) + (d.subsec_nanos() as f64)/1e9f64
+}
+
+// Everything we write to disk is little endian.
+pub fn write_u64<W: std::io::Write>(writer: &mut W, value: u64) -> std::io::Result<()> {
+    let bytes = (0..8).map(|i| (value >> (8 * i)) as u8).collect::<Vec<u8>>();
+    writer.write_all(bytes.as_ref())
+}
+
+pub fn read_u64<R: std::io::Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes.iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+}
+
+// Reads len bytes, or None if the input ends first. len comes from a file header, so it isn't trusted
+// enough to allocate up front: a corrupt one just runs out of input.
+pub fn read_bytes<R: std::io::Read>(reader: &mut R, len: u64) -> std::io::Result<Option<Vec<u8>>> {
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut bytes)?;
+    Ok(if bytes.len() as u64 == len { Some(bytes) } else { None })
+}
+
+// Seven bits at a time, low bits first, with the top bit set on every byte but the last.
+pub fn write_varint(out: &mut Vec<u8>, value: usize) {
+    let mut value = value;
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub fn read_varint(bytes: &[u8], offset: &mut usize) -> Option<usize> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*offset)?;
+        *offset += 1;
+        if shift >= std::mem::size_of::<usize>() * 8 {
+            return None;
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
 pub struct UncheckedFixedSizeStack<T> {
     data_store: std::ptr::Unique<T>,
     len: isize,
@@ -211,6 +322,13 @@ mod test {
 
     }
 
+    #[test]
+    fn dense_symbols_test() {
+        let data = [0xFFFFFFF0u32, 7, 0xFFFFFFF0, 1 << 20, 7];
+        assert_eq!(super::dense_symbols(data.as_ref()).as_ref(), &[2, 0, 2, 1, 0]);
+        assert_eq!(super::dense_symbols::<u16>(&[]).as_ref(), &[] as &[u16]);
+    }
+
     #[test]
     fn playground() {
     }